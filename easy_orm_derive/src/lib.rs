@@ -0,0 +1,210 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, Ident, LitStr, Result,
+    Type,
+};
+
+/// Derives the same items as `data_table!` for a plain struct, which is used
+/// as the entity's `Row`. The entity itself is exported as `{Struct}Entity`.
+///
+/// ```ignore
+/// #[derive(Debug, Clone, sqlx::FromRow, Entity)]
+/// #[orm(table = "people")]
+/// pub struct Person {
+///     #[orm(primary_key)]
+///     pub id: i64,
+///     pub first_name: String,
+///     #[orm(default)]
+///     pub is_active: bool,
+///     #[orm(references = "Circle.id", relation = "HomeCircle")]
+///     pub circle_id: Option<i64>,
+/// }
+/// ```
+#[proc_macro_derive(Entity, attributes(orm))]
+pub fn derive_entity(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+struct Reference {
+    entity: Ident,
+    col: Ident,
+    relation: Option<Ident>,
+}
+
+struct Column {
+    name: Ident,
+    ty: Type,
+    primary_key: bool,
+    default: bool,
+    reference: Option<Reference>,
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "`Entity` can't be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "`Entity` requires a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "`Entity` can only be derived for structs",
+            ))
+        }
+    };
+
+    let table = parse_table(&input)?;
+    let mut id = None;
+    let mut cols = Vec::new();
+    for field in fields {
+        let col = parse_column(field)?;
+        if col.primary_key {
+            if id.is_some() {
+                return Err(Error::new(
+                    col.name.span(),
+                    "only one column can be the primary key",
+                ));
+            }
+            id = Some(col);
+        } else {
+            cols.push(col);
+        }
+    }
+
+    let model = &input.ident;
+    let entity = format_ident!("{}Entity", model);
+    let id = id.map(|Column { name, ty, .. }| quote!([#name: #ty],));
+    let cols = cols.iter().map(|col| {
+        let Column { name, ty, .. } = col;
+        let default = col.default.then(|| quote!(= default));
+        let reference = col.reference.as_ref().map(|r| {
+            let Reference {
+                entity,
+                col,
+                relation,
+            } = r;
+            let relation = relation.as_ref().map(|rel| quote!(as #rel));
+            quote!(=> #entity.#col #relation)
+        });
+        quote!(#name: #ty #default #reference)
+    });
+    let cols = quote!(#id #(#cols),*);
+
+    Ok(quote! {
+        ::easy_orm::data_table!(@items #model as #entity of #table [
+            pub type Row = super::#model;
+        ] [#cols] {
+            #cols
+        });
+    })
+}
+
+/// The table name is passed on as the string literal, so it needn't be an
+/// identifier.
+fn parse_table(input: &DeriveInput) -> Result<LitStr> {
+    let mut table = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("orm")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                let lit: LitStr = meta.value()?.parse()?;
+                if lit.value().is_empty() {
+                    return Err(Error::new(lit.span(), "the table name can't be empty"));
+                }
+                table = Some(lit);
+                Ok(())
+            } else {
+                Err(meta.error("expected `table = \"...\"`"))
+            }
+        })?;
+    }
+    table.ok_or_else(|| {
+        Error::new(
+            input.ident.span(),
+            "missing `#[orm(table = \"...\")]` attribute",
+        )
+    })
+}
+
+fn parse_column(field: &syn::Field) -> Result<Column> {
+    let name = field.ident.clone().expect("named field");
+    let mut col = Column {
+        name,
+        ty: field.ty.clone(),
+        primary_key: false,
+        default: false,
+        reference: None,
+    };
+    let mut relation = None;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("orm")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("primary_key") {
+                col.primary_key = true;
+            } else if meta.path.is_ident("default") {
+                col.default = true;
+            } else if meta.path.is_ident("references") {
+                let lit: LitStr = meta.value()?.parse()?;
+                let value = lit.value();
+                let Some((entity, target)) = value.split_once('.') else {
+                    return Err(Error::new(lit.span(), "expected `\"Entity.column\"`"));
+                };
+                col.reference = Some(Reference {
+                    entity: parse_ident(entity, lit.span())?,
+                    col: parse_ident(target, lit.span())?,
+                    relation: None,
+                });
+            } else if meta.path.is_ident("relation") {
+                let lit: LitStr = meta.value()?.parse()?;
+                relation = Some(parse_ident(&lit.value(), lit.span())?);
+            } else {
+                return Err(
+                    meta.error("expected `primary_key`, `default`, `references` or `relation`")
+                );
+            }
+            Ok(())
+        })?;
+    }
+
+    if col.primary_key && (col.default || col.reference.is_some()) {
+        return Err(Error::new(
+            col.name.span(),
+            "a primary key can't have `default` or `references`",
+        ));
+    }
+    match (&mut col.reference, relation) {
+        (Some(reference), relation) => reference.relation = relation,
+        (None, Some(relation)) => {
+            return Err(Error::new(
+                relation.span(),
+                "`relation` requires `references`",
+            ))
+        }
+        (None, None) => {}
+    }
+    Ok(col)
+}
+
+/// Parses `name` as an identifier, or as a raw one if it is a keyword such
+/// as `type`, which a column named `type` is declared with.
+fn parse_ident(name: &str, span: Span) -> Result<Ident> {
+    let mut ident = syn::parse_str::<Ident>(name)
+        .or_else(|_| syn::parse_str::<Ident>(&format!("r#{}", name)))
+        .map_err(|_| Error::new(span, format!("`{}` is not an identifier", name)))?;
+    ident.set_span(span);
+    Ok(ident)
+}
@@ -0,0 +1,2 @@
+CREATE UNIQUE INDEX people_external_identity
+  ON people (external_identity_provider, external_identity_number);
@@ -0,0 +1,265 @@
+use std::marker::PhantomData;
+
+use sqlx::{Decode, Type};
+
+use crate::{
+    backend::Backend,
+    common::{Decoder, Selector},
+    sql::Col,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AggFn {
+    Count,
+    CountDistinct,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggFn {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            AggFn::Count => "count",
+            AggFn::CountDistinct => "count_distinct",
+            AggFn::Sum => "sum",
+            AggFn::Avg => "avg",
+            AggFn::Min => "min",
+            AggFn::Max => "max",
+        }
+    }
+}
+
+/// A type an aggregate is cast to, named per backend.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Cast {
+    BigInt,
+    Double,
+}
+
+impl Cast {
+    pub(crate) fn name<DB: Backend>(&self) -> &'static str {
+        match self {
+            Cast::BigInt => DB::BIGINT,
+            Cast::Double => DB::DOUBLE,
+        }
+    }
+}
+
+/// An aggregate function applied to a column, optionally cast so that its
+/// result decodes into the Rust type the column's type maps to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Aggregate {
+    pub(crate) func: AggFn,
+    pub(crate) cast: Option<Cast>,
+}
+
+/// Column types that can be selected through `min` and `max`. `Inner` is the
+/// type without the `Option` of nullable columns.
+pub trait Scalar {
+    type Inner;
+}
+
+/// Column types that can be summed and averaged.
+///
+/// Postgres widens `SUM(BIGINT)` and `AVG` of integers to `NUMERIC`, and
+/// MySQL any integer sum, so those are cast back to a 64-bit integer and a
+/// double respectively.
+pub trait Numeric: Scalar {
+    type Sum;
+    type Avg;
+    const SUM_CAST: Option<Cast>;
+    const AVG_CAST: Option<Cast>;
+}
+
+macro_rules! impl_scalar {
+    ($($t:ty),*) => {
+        $(impl Scalar for $t {
+            type Inner = $t;
+        })*
+    };
+}
+
+impl_scalar!(bool, i8, i16, i32, i64, f32, f64, String, Vec<u8>);
+
+impl<T: Scalar> Scalar for Option<T> {
+    type Inner = T::Inner;
+}
+
+macro_rules! impl_numeric {
+    ($($t:ty => $sum:ty, $sum_cast:expr; $avg:ty, $avg_cast:expr);* $(;)?) => {
+        $(impl Numeric for $t {
+            type Sum = $sum;
+            type Avg = $avg;
+            const SUM_CAST: Option<Cast> = $sum_cast;
+            const AVG_CAST: Option<Cast> = $avg_cast;
+        })*
+    };
+}
+
+impl_numeric!(
+    i16 => i64, Some(Cast::BigInt); f64, Some(Cast::Double);
+    i32 => i64, Some(Cast::BigInt); f64, Some(Cast::Double);
+    i64 => i64, Some(Cast::BigInt); f64, Some(Cast::Double);
+    f32 => f32, None; f64, None;
+    f64 => f64, None; f64, None;
+);
+
+impl<T: Numeric> Numeric for Option<T> {
+    type Sum = T::Sum;
+    type Avg = T::Avg;
+    const SUM_CAST: Option<Cast> = T::SUM_CAST;
+    const AVG_CAST: Option<Cast> = T::AVG_CAST;
+}
+
+macro_rules! def_aggregate {
+    ($(#[$doc:meta])* $name:ident, $func:ident, $bound:ident, |$c:ident| $data:ty, $cast:expr) => {
+        $(#[$doc])*
+        pub struct $name<C> {
+            col: Col,
+            _pha: PhantomData<C>,
+        }
+
+        impl<C> From<$name<C>> for Col {
+            fn from(val: $name<C>) -> Self {
+                val.col
+            }
+        }
+
+        impl<$c> Selector for $name<$c>
+        where
+            $c: Selector,
+            $c::Data: $bound,
+        {
+            type Data = $data;
+            fn cols(&self) -> impl Iterator<Item = Col> {
+                std::iter::once(self.col.clone())
+            }
+        }
+
+        impl<DB, $c> Decoder<DB> for $name<$c>
+        where
+            DB: Backend,
+            $c: Selector,
+            $c::Data: $bound,
+            $data: for<'r> Decode<'r, DB> + Type<DB>,
+        {
+            fn from_row(&self, row: &DB::Row) -> Result<Self::Data, sqlx::Error> {
+                DB::try_get(row, self.col.alias().as_str())
+            }
+        }
+
+        impl<$c> $name<$c>
+        where
+            $c: Selector,
+            $c::Data: $bound,
+        {
+            fn new(col: impl Into<Col>) -> Self {
+                let agg = Aggregate {
+                    func: AggFn::$func,
+                    cast: $cast,
+                };
+                Self {
+                    col: col.into().aggregate(agg),
+                    _pha: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+def_aggregate!(
+    /// `COUNT(col)`, the number of rows where `col` is not `NULL`.
+    Count, Count, Sized, |C| i64, None
+);
+def_aggregate!(
+    /// `COUNT(DISTINCT col)`.
+    CountDistinct, CountDistinct, Sized, |C| i64, None
+);
+def_aggregate!(
+    /// `SUM(col)`, `None` when there are no non-`NULL` values.
+    Sum, Sum, Numeric, |C| Option<<C::Data as Numeric>::Sum>, <C::Data as Numeric>::SUM_CAST
+);
+def_aggregate!(
+    /// `AVG(col)`, `None` when there are no non-`NULL` values.
+    Avg, Avg, Numeric, |C| Option<<C::Data as Numeric>::Avg>, <C::Data as Numeric>::AVG_CAST
+);
+def_aggregate!(
+    /// `MIN(col)`, `None` when there are no non-`NULL` values.
+    Min, Min, Scalar, |C| Option<<C::Data as Scalar>::Inner>, None
+);
+def_aggregate!(
+    /// `MAX(col)`, `None` when there are no non-`NULL` values.
+    Max, Max, Scalar, |C| Option<<C::Data as Scalar>::Inner>, None
+);
+
+pub fn count<C: Selector + Into<Col>>(col: C) -> Count<C> {
+    Count::new(col)
+}
+
+pub fn count_distinct<C: Selector + Into<Col>>(col: C) -> CountDistinct<C> {
+    CountDistinct::new(col)
+}
+
+pub fn sum<C>(col: C) -> Sum<C>
+where
+    C: Selector + Into<Col>,
+    C::Data: Numeric,
+{
+    Sum::new(col)
+}
+
+pub fn avg<C>(col: C) -> Avg<C>
+where
+    C: Selector + Into<Col>,
+    C::Data: Numeric,
+{
+    Avg::new(col)
+}
+
+pub fn min<C>(col: C) -> Min<C>
+where
+    C: Selector + Into<Col>,
+    C::Data: Scalar,
+{
+    Min::new(col)
+}
+
+pub fn max<C>(col: C) -> Max<C>
+where
+    C: Selector + Into<Col>,
+    C::Data: Scalar,
+{
+    Max::new(col)
+}
+
+/// `COUNT(*)`.
+pub struct CountStar;
+
+impl From<CountStar> for Col {
+    fn from(_: CountStar) -> Self {
+        Col::new("".into(), "*".into()).aggregate(Aggregate {
+            func: AggFn::Count,
+            cast: None,
+        })
+    }
+}
+
+impl Selector for CountStar {
+    type Data = i64;
+    fn cols(&self) -> impl Iterator<Item = Col> {
+        std::iter::once(CountStar.into())
+    }
+}
+
+impl<DB: Backend> Decoder<DB> for CountStar {
+    fn from_row(&self, row: &DB::Row) -> Result<Self::Data, sqlx::Error> {
+        let col: Col = CountStar.into();
+        DB::get_i64(row, col.alias().as_str())
+    }
+}
+
+pub fn count_star() -> CountStar {
+    CountStar
+}
@@ -0,0 +1,465 @@
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use sqlx::{Database, Decode, Encode, Executor, QueryBuilder, Row, Type, ValueRef};
+
+use crate::sql::{push_returning, Col, ToSql};
+
+/// A database the generated queries can run on.
+///
+/// Bind placeholders (`$1` or `?`) are rendered by sqlx's `QueryBuilder` for
+/// the backend, so the SQL built by this crate never spells them out. The
+/// methods wrap the few sqlx calls whose bounds can only be proven for a
+/// concrete database.
+pub trait Backend: Database {
+    /// Whether `INSERT`, `UPDATE` and `DELETE` accept a `RETURNING` clause.
+    const RETURNING: bool;
+
+    /// Whether upserts are written `ON DUPLICATE KEY UPDATE`, which takes a
+    /// conflict on any unique key, rather than `ON CONFLICT (target)`.
+    const ON_DUPLICATE_KEY: bool;
+
+    /// Whether `DEFAULT` may stand for a value in a multi-row `VALUES` list.
+    const DEFAULT_IN_VALUES: bool;
+
+    /// The operator for case-insensitive `LIKE`.
+    const ILIKE: &'static str;
+
+    /// Appended to `LIKE` patterns escaped with backslashes, for databases
+    /// that have no escape character by default.
+    const LIKE_ESCAPE: &'static str;
+
+    /// The null-safe `=`, for backends without `IS DISTINCT FROM`, which is
+    /// then rendered as `NOT (a <=> b)`.
+    const NULL_SAFE_EQ: Option<&'static str>;
+
+    /// Whether `ORDER BY` accepts `NULLS FIRST` and `NULLS LAST`. Otherwise
+    /// the column is sorted by `col IS NULL` first.
+    const NULLS_ORDER: bool;
+
+    /// The `LIMIT` that stands for no limit, for backends that don't accept
+    /// an `OFFSET` without a `LIMIT`.
+    const NO_LIMIT: Option<&'static str>;
+
+    /// The maximum number of bind parameters accepted in one statement.
+    const BIND_LIMIT: usize;
+
+    /// The character identifiers are quoted with.
+    const QUOTE: char;
+
+    /// What follows `INSERT INTO table` to insert a row of defaults.
+    const DEFAULT_VALUES: &'static str;
+
+    /// The names of the 64-bit integer and floating point types in `CAST`.
+    const BIGINT: &'static str;
+    const DOUBLE: &'static str;
+
+    /// What a connection of this backend is used as to run queries.
+    type Exec<'c>: Executor<'c, Database = Self>
+    where
+        Self: 'c;
+
+    fn executor(conn: &mut Self::Connection) -> Self::Exec<'_>;
+
+    fn fetch<'e, 'c: 'e, 'q: 'e, E>(
+        query: &'e mut QueryBuilder<'q, Self>,
+        e: E,
+    ) -> BoxStream<'e, Result<Self::Row, sqlx::Error>>
+    where
+        E: 'e + Executor<'c, Database = Self>;
+
+    /// Runs the query, returning the number of rows affected.
+    fn execute<'e, 'c: 'e, 'q: 'e, E>(
+        query: &'e mut QueryBuilder<'q, Self>,
+        e: E,
+    ) -> BoxFuture<'e, Result<u64, sqlx::Error>>
+    where
+        E: 'e + Executor<'c, Database = Self>;
+
+    /// Decodes the column named `col`.
+    fn try_get<T>(row: &Self::Row, col: &str) -> Result<T, sqlx::Error>
+    where
+        T: for<'r> Decode<'r, Self> + Type<Self>;
+
+    fn is_null(row: &Self::Row, col: &str) -> Result<bool, sqlx::Error>;
+
+    /// Binds an integer argument, such as a `LIMIT`.
+    fn push_bind_i64(query: &mut QueryBuilder<'_, Self>, val: i64);
+
+    fn get_i64(row: &Self::Row, col: &str) -> Result<i64, sqlx::Error>;
+
+    fn get_bool(row: &Self::Row, col: &str) -> Result<bool, sqlx::Error>;
+}
+
+/// Rust types that can be both bound to and decoded from queries on `DB`,
+/// i.e. the types columns can be declared with.
+pub trait SqlType<DB: Database>:
+    for<'q> Encode<'q, DB> + for<'r> Decode<'r, DB> + Type<DB>
+{
+}
+
+impl<DB: Database, T> SqlType<DB> for T where
+    T: for<'q> Encode<'q, DB> + for<'r> Decode<'r, DB> + Type<DB>
+{
+}
+
+// The bodies are the same for every backend, but only type check once the
+// database is concrete.
+macro_rules! impl_backend_calls {
+    ($conn:ty) => {
+        type Exec<'c> = &'c mut $conn;
+
+        fn executor(conn: &mut $conn) -> Self::Exec<'_> {
+            conn
+        }
+
+        fn fetch<'e, 'c: 'e, 'q: 'e, E>(
+            query: &'e mut QueryBuilder<'q, Self>,
+            e: E,
+        ) -> BoxStream<'e, Result<Self::Row, sqlx::Error>>
+        where
+            E: 'e + Executor<'c, Database = Self>,
+        {
+            query.build().fetch(e)
+        }
+
+        fn execute<'e, 'c: 'e, 'q: 'e, E>(
+            query: &'e mut QueryBuilder<'q, Self>,
+            e: E,
+        ) -> BoxFuture<'e, Result<u64, sqlx::Error>>
+        where
+            E: 'e + Executor<'c, Database = Self>,
+        {
+            Box::pin(async move { Ok(query.build().execute(e).await?.rows_affected()) })
+        }
+
+        fn try_get<T>(row: &Self::Row, col: &str) -> Result<T, sqlx::Error>
+        where
+            T: for<'r> Decode<'r, Self> + Type<Self>,
+        {
+            row.try_get(col)
+        }
+
+        fn is_null(row: &Self::Row, col: &str) -> Result<bool, sqlx::Error> {
+            Ok(row.try_get_raw(col)?.is_null())
+        }
+
+        fn push_bind_i64(query: &mut QueryBuilder<'_, Self>, val: i64) {
+            query.push_bind(val);
+        }
+
+        fn get_i64(row: &Self::Row, col: &str) -> Result<i64, sqlx::Error> {
+            row.try_get(col)
+        }
+    };
+}
+
+impl Backend for sqlx::Postgres {
+    const RETURNING: bool = true;
+    const ON_DUPLICATE_KEY: bool = false;
+    const DEFAULT_IN_VALUES: bool = true;
+    const ILIKE: &'static str = "ILIKE";
+    const LIKE_ESCAPE: &'static str = "";
+    const NULL_SAFE_EQ: Option<&'static str> = None;
+    const NULLS_ORDER: bool = true;
+    const NO_LIMIT: Option<&'static str> = None;
+    const BIND_LIMIT: usize = u16::MAX as usize;
+    const QUOTE: char = '"';
+    const DEFAULT_VALUES: &'static str = "DEFAULT VALUES";
+    const BIGINT: &'static str = "BIGINT";
+    const DOUBLE: &'static str = "DOUBLE PRECISION";
+
+    impl_backend_calls!(sqlx::PgConnection);
+
+    fn get_bool(row: &Self::Row, col: &str) -> Result<bool, sqlx::Error> {
+        row.try_get(col)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Backend for sqlx::Sqlite {
+    const RETURNING: bool = true;
+    const ON_DUPLICATE_KEY: bool = false;
+    const DEFAULT_IN_VALUES: bool = false;
+    // `LIKE` already ignores ASCII case in SQLite.
+    const ILIKE: &'static str = "LIKE";
+    const LIKE_ESCAPE: &'static str = " ESCAPE '\\'";
+    const NULL_SAFE_EQ: Option<&'static str> = None;
+    const NULLS_ORDER: bool = true;
+    const NO_LIMIT: Option<&'static str> = Some("-1");
+    // `SQLITE_MAX_VARIABLE_NUMBER` since SQLite 3.32.
+    const BIND_LIMIT: usize = 32766;
+    const QUOTE: char = '"';
+    const DEFAULT_VALUES: &'static str = "DEFAULT VALUES";
+    const BIGINT: &'static str = "BIGINT";
+    const DOUBLE: &'static str = "DOUBLE PRECISION";
+
+    impl_backend_calls!(sqlx::SqliteConnection);
+
+    fn get_bool(row: &Self::Row, col: &str) -> Result<bool, sqlx::Error> {
+        row.try_get(col)
+    }
+}
+
+#[cfg(feature = "mysql")]
+impl Backend for sqlx::MySql {
+    const RETURNING: bool = false;
+    const ON_DUPLICATE_KEY: bool = true;
+    const DEFAULT_IN_VALUES: bool = true;
+    // `LIKE` ignores case under the default collations.
+    const ILIKE: &'static str = "LIKE";
+    const LIKE_ESCAPE: &'static str = "";
+    const NULL_SAFE_EQ: Option<&'static str> = Some("<=>");
+    const NULLS_ORDER: bool = false;
+    // The largest `BIGINT UNSIGNED`, as the MySQL manual suggests.
+    const NO_LIMIT: Option<&'static str> = Some("18446744073709551615");
+    const BIND_LIMIT: usize = u16::MAX as usize;
+    const QUOTE: char = '`';
+    const DEFAULT_VALUES: &'static str = "() VALUES ()";
+    const BIGINT: &'static str = "SIGNED";
+    const DOUBLE: &'static str = "DOUBLE";
+
+    impl_backend_calls!(sqlx::MySqlConnection);
+
+    // Predicates such as `EXISTS` are integers on MySQL.
+    fn get_bool(row: &Self::Row, col: &str) -> Result<bool, sqlx::Error> {
+        Ok(row.try_get::<i64, _>(col)? != 0)
+    }
+}
+
+/// Backends with `RETURNING`, which the `returning` methods are limited to.
+pub trait SupportsReturning: Backend {}
+
+impl SupportsReturning for sqlx::Postgres {}
+
+#[cfg(feature = "sqlite")]
+impl SupportsReturning for sqlx::Sqlite {}
+
+/// Types the id column of an inserted row can be read back as on `DB`.
+///
+/// Backends with `RETURNING` select the column back, which works for any
+/// column type. MySQL reports the `LAST_INSERT_ID()` of the statement
+/// instead, so its ids have to be `AUTO_INCREMENT` integers, which are never
+/// 0. An id of 0 is taken for no row written.
+pub trait InsertId<DB: Backend>: Sized {
+    /// Runs the `INSERT`, returning the id generated for the row in `col`,
+    /// or `None` if no row was written, e.g. by an ignored upsert.
+    fn insert_id<'e, 'c: 'e, 'q: 'e, E>(
+        query: &'e mut QueryBuilder<'q, DB>,
+        col: Col,
+        e: E,
+    ) -> BoxFuture<'e, Result<Option<Self>, sqlx::Error>>
+    where
+        E: 'e + Executor<'c, Database = DB>;
+
+    /// Runs a multi-row `INSERT` of `rows` rows, returning their ids in
+    /// order.
+    fn insert_ids<'e, 'c: 'e, 'q: 'e, E>(
+        query: QueryBuilder<'q, DB>,
+        col: Col,
+        rows: usize,
+        e: E,
+    ) -> BoxFuture<'e, Result<Vec<Self>, sqlx::Error>>
+    where
+        E: 'e + Executor<'c, Database = DB>;
+}
+
+macro_rules! impl_returning_insert_id {
+    ($db:ty) => {
+        impl<T: SqlType<$db> + Send> InsertId<$db> for T {
+            fn insert_id<'e, 'c: 'e, 'q: 'e, E>(
+                query: &'e mut QueryBuilder<'q, $db>,
+                col: Col,
+                e: E,
+            ) -> BoxFuture<'e, Result<Option<Self>, sqlx::Error>>
+            where
+                E: 'e + Executor<'c, Database = $db>,
+            {
+                Box::pin(async move {
+                    let alias = col.alias();
+                    push_returning(query, [col]);
+                    fetch_optional(query, e, |row| row.try_get(alias.as_str())).await
+                })
+            }
+
+            fn insert_ids<'e, 'c: 'e, 'q: 'e, E>(
+                mut query: QueryBuilder<'q, $db>,
+                col: Col,
+                _: usize,
+                e: E,
+            ) -> BoxFuture<'e, Result<Vec<Self>, sqlx::Error>>
+            where
+                E: 'e + Executor<'c, Database = $db>,
+            {
+                Box::pin(async move {
+                    let alias = col.alias();
+                    push_returning(&mut query, [col]);
+                    fetch_all(query, e, |row| row.try_get(alias.as_str())).await
+                })
+            }
+        }
+    };
+}
+
+impl_returning_insert_id!(sqlx::Postgres);
+
+#[cfg(feature = "sqlite")]
+impl_returning_insert_id!(sqlx::Sqlite);
+
+#[cfg(feature = "mysql")]
+impl<T> InsertId<sqlx::MySql> for T
+where
+    T: TryFrom<u64> + Send,
+    T::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn insert_id<'e, 'c: 'e, 'q: 'e, E>(
+        query: &'e mut QueryBuilder<'q, sqlx::MySql>,
+        _: Col,
+        e: E,
+    ) -> BoxFuture<'e, Result<Option<Self>, sqlx::Error>>
+    where
+        E: 'e + Executor<'c, Database = sqlx::MySql>,
+    {
+        Box::pin(async move {
+            let res = query.build().execute(e).await?;
+            // An upsert that left the row untouched still counts it as
+            // affected, as sqlx asks for the rows found, but reports no id.
+            if res.rows_affected() == 0 || res.last_insert_id() == 0 {
+                return Ok(None);
+            }
+            let id =
+                T::try_from(res.last_insert_id()).map_err(|e| sqlx::Error::Decode(e.into()))?;
+            Ok(Some(id))
+        })
+    }
+
+    fn insert_ids<'e, 'c: 'e, 'q: 'e, E>(
+        mut query: QueryBuilder<'q, sqlx::MySql>,
+        _: Col,
+        rows: usize,
+        e: E,
+    ) -> BoxFuture<'e, Result<Vec<Self>, sqlx::Error>>
+    where
+        E: 'e + Executor<'c, Database = sqlx::MySql>,
+    {
+        Box::pin(async move {
+            // A multi-row insert reports the id of its first row, and
+            // allocates the ids of the rest consecutively.
+            let first = query.build().execute(e).await?.last_insert_id();
+            (first..)
+                .take(rows)
+                .map(|id| T::try_from(id).map_err(|e| sqlx::Error::Decode(e.into())))
+                .collect()
+        })
+    }
+}
+
+/// Collections of values that `in_list` and `not_in` match a column against
+/// on `DB`.
+///
+/// Postgres binds the whole collection as a single array, rendering
+/// `col = ANY($1)`, so any number of values fits in one statement. The other
+/// backends bind each value, rendering `col IN ($1, $2, ..)`.
+pub trait InList<'q, DB: Backend> {
+    /// How many values fit in one statement.
+    const MAX_LEN: usize;
+
+    fn push_in_list(self, query: &mut QueryBuilder<'q, DB>, col: &Col, negated: bool);
+}
+
+impl<'q, T> InList<'q, sqlx::Postgres> for T
+where
+    T: 'q + Encode<'q, sqlx::Postgres> + Type<sqlx::Postgres>,
+{
+    const MAX_LEN: usize = usize::MAX;
+
+    fn push_in_list(self, query: &mut QueryBuilder<'q, sqlx::Postgres>, col: &Col, negated: bool) {
+        let op = if negated { "<> ALL" } else { "= ANY" };
+        query.push(format!(" {} {}(", col.sql::<sqlx::Postgres>(), op));
+        query.push_bind(self);
+        query.push(")");
+    }
+}
+
+#[cfg(any(feature = "sqlite", feature = "mysql"))]
+macro_rules! impl_bind_each_in_list {
+    ($db:ty) => {
+        impl<'q, T> InList<'q, $db> for T
+        where
+            T: IntoIterator,
+            T::Item: 'q + Encode<'q, $db> + Type<$db>,
+        {
+            const MAX_LEN: usize = <$db as Backend>::BIND_LIMIT;
+
+            fn push_in_list(self, query: &mut QueryBuilder<'q, $db>, col: &Col, negated: bool) {
+                let mut vals = self.into_iter().peekable();
+                if vals.peek().is_none() {
+                    query.push(if negated { " 1 = 1" } else { " 1 = 0" });
+                    return;
+                }
+                let op = if negated { "NOT IN" } else { "IN" };
+                query.push(format!(" {} {} (", col.sql::<$db>(), op));
+                let mut sep = query.separated(", ");
+                for val in vals {
+                    sep.push_bind(val);
+                }
+                query.push(")");
+            }
+        }
+    };
+}
+
+#[cfg(feature = "sqlite")]
+impl_bind_each_in_list!(sqlx::Sqlite);
+
+#[cfg(feature = "mysql")]
+impl_bind_each_in_list!(sqlx::MySql);
+
+/// Runs the query and decodes its first row with `f`.
+pub async fn fetch_one<'c, 'q, DB, E, T>(
+    mut query: QueryBuilder<'q, DB>,
+    e: E,
+    f: impl Fn(&DB::Row) -> Result<T, sqlx::Error>,
+) -> Result<T, sqlx::Error>
+where
+    DB: Backend,
+    E: Executor<'c, Database = DB>,
+{
+    match fetch_optional(&mut query, e, f).await? {
+        Some(val) => Ok(val),
+        None => Err(sqlx::Error::RowNotFound),
+    }
+}
+
+/// Runs the query and decodes its first row, if any, with `f`.
+pub async fn fetch_optional<'c, 'q, DB, E, T>(
+    query: &mut QueryBuilder<'q, DB>,
+    e: E,
+    f: impl Fn(&DB::Row) -> Result<T, sqlx::Error>,
+) -> Result<Option<T>, sqlx::Error>
+where
+    DB: Backend,
+    E: Executor<'c, Database = DB>,
+{
+    let mut rows = DB::fetch(query, e);
+    match rows.next().await {
+        Some(row) => f(&row?).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Runs the query and decodes every row with `f`.
+pub async fn fetch_all<'c, 'q, DB, E, T>(
+    mut query: QueryBuilder<'q, DB>,
+    e: E,
+    f: impl Fn(&DB::Row) -> Result<T, sqlx::Error>,
+) -> Result<Vec<T>, sqlx::Error>
+where
+    DB: Backend,
+    E: Executor<'c, Database = DB>,
+{
+    let mut rows = DB::fetch(&mut query, e);
+    let mut all = Vec::new();
+    while let Some(row) = rows.next().await {
+        all.push(f(&row?)?);
+    }
+    Ok(all)
+}
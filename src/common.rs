@@ -1,51 +1,164 @@
+use std::marker::PhantomData;
 
-use sqlx::{postgres::PgRow, FromRow};
+use sqlx::{postgres::PgRow, query_builder::Separated, Decode, FromRow, Type};
 
 use crate::{
-    relations::Related,
-    sql::{Col, JoinTy, Select},
+    backend::Backend,
+    error::Error,
+    relations::{Related, RelationTrait},
+    sql::{Col, Delete, Iden, JoinTy, Select},
 };
 
 pub trait EntityTrait {
     const TABLE_NAME: &'static str;
     type Row;
 
+    /// All columns of the table, qualified by `tbl`, which is either
+    /// `TABLE_NAME` or an alias of it.
+    fn columns(tbl: &Iden) -> Vec<Col>;
+
+    /// Refers to the table under another name, so it can appear several
+    /// times in one `Select`, e.g. `Circle::alias("parent")`.
+    fn alias(name: &str) -> TableAlias<Self>
+    where
+        Self: Sized,
+    {
+        TableAlias {
+            name: name.into(),
+            _pha: PhantomData,
+        }
+    }
+
     fn all_col() -> Col {
         Col::new(Self::TABLE_NAME.into(), "*".into())
     }
 
     fn find() -> Select<Self>
+    where
+        Self: Sized + Default,
+    {
+        Select::new(Self::TABLE_NAME.into(), Self::default())
+    }
+
+    fn delete() -> Delete<Self>
     where
         Self: Sized,
     {
-        Select::new(Self::TABLE_NAME.into())
+        Delete::new(Self::TABLE_NAME.into())
     }
 
     fn find_related<E>() -> Select<E>
     where
         Self: Related<E>,
-        E: EntityTrait,
+        E: EntityTrait + Default,
     {
-        let mut sql = Select::new(E::TABLE_NAME.into());
+        let mut sql = Select::new(E::TABLE_NAME.into(), E::default());
         if let Some(via) = Self::via() {
             sql = sql.join(JoinTy::Left, via)
         }
         sql.join(JoinTy::Left, Self::to())
     }
+
+    /// Like `find_related`, but joins through the given relation, which may
+    /// be defined on either side. Needed when there are several relations
+    /// between the same entities, e.g. `Relation::SuperCircle`.
+    ///
+    /// Fails with `Error::SelfRelation` for a relation of a table to itself,
+    /// which has to be joined under an alias with `find_related_via_as`, and
+    /// with `Error::RelationMismatch` if `rel` doesn't involve `Self`.
+    fn find_related_via<E>(rel: impl RelationTrait) -> Result<Select<E>, Error>
+    where
+        Self: Sized,
+        E: EntityTrait + Default,
+    {
+        let rel = rel.def();
+        if rel.from_col.tbl == rel.to_col.tbl {
+            return Err(Error::SelfRelation {
+                table: rel.from_col.tbl.as_str().to_string(),
+            });
+        }
+        let rel = if rel.from_col.tbl.as_str() == Self::TABLE_NAME {
+            rel
+        } else if rel.to_col.tbl.as_str() == Self::TABLE_NAME {
+            rel.rev()
+        } else {
+            return Err(Error::RelationMismatch {
+                from: rel.from_col.tbl.as_str().to_string(),
+                table: Self::TABLE_NAME.to_string(),
+            });
+        };
+        Ok(Select::new(E::TABLE_NAME.into(), E::default()).join(JoinTy::Left, rel))
+    }
+
+    /// Like `find_related_via`, but joins `Self` under `alias`, whose columns
+    /// are then filtered on with `alias.col`.
+    ///
+    /// Both sides of a relation of a table to itself are the same table, so
+    /// `rel` is taken as is, with `Self` on its `from_col` side. With
+    /// `super_circle_id => Circle.id as SuperCircle`,
+    /// `Circle::find_related_via_as::<Circle>(Relation::SuperCircle, &child)`
+    /// selects the super circles of `child`, and passing
+    /// `Relation::SuperCircle.def().rev()` selects its sub circles.
+    ///
+    /// Fails with `Error::RelationMismatch` if `rel` doesn't involve `Self`.
+    fn find_related_via_as<E>(
+        rel: impl RelationTrait,
+        alias: &TableAlias<Self>,
+    ) -> Result<Select<E>, Error>
+    where
+        Self: Sized,
+        E: EntityTrait + Default,
+    {
+        let rel = rel.def();
+        let rel = if rel.from_col.tbl == rel.to_col.tbl
+            || rel.from_col.tbl.as_str() == Self::TABLE_NAME
+        {
+            rel
+        } else {
+            rel.rev()
+        };
+        Select::new(E::TABLE_NAME.into(), E::default()).join_as(JoinTy::Left, rel, alias)
+    }
+}
+
+/// Decodes the rows of an entity of a `data_table!` on the backend `DB`.
+pub trait DecodeEntity<DB: Backend>: EntityTrait {
+    /// Decodes a row whose columns were selected from `tbl`.
+    fn decode(row: &DB::Row, tbl: &Iden) -> Result<Self::Row, sqlx::Error>;
 }
 
 pub trait Selector {
     type Data;
-    fn cols() -> impl Iterator<Item = Col>;
-    fn from_row(row: &PgRow) -> Result<Self::Data, sqlx::Error>;
+    fn cols(&self) -> impl Iterator<Item = Col>;
+}
+
+/// A column marker of a `data_table!`, e.g. `Circle::Name`.
+pub trait Column: Selector {
+    /// The entity of the table the column belongs to.
+    type Entity: EntityTrait;
+}
+
+/// A selector whose columns can be decoded from the rows of `DB`.
+pub trait Decoder<DB: Backend>: Selector {
+    #[allow(clippy::wrong_self_convention)]
+    fn from_row(&self, row: &DB::Row) -> Result<Self::Data, sqlx::Error>;
+}
+
+/// A selector whose decoded data can be bound back into a query, in the same
+/// order as its columns. Used as the cursor of keyset pagination.
+pub trait Keyset<'q, DB: Backend>: Selector {
+    fn push_values(data: Self::Data, sep: &mut Separated<'_, 'q, DB, &'static str>);
 }
 
 impl Selector for () {
     type Data = ();
-    fn cols() -> impl Iterator<Item = Col> {
+    fn cols(&self) -> impl Iterator<Item = Col> {
         std::iter::empty()
     }
-    fn from_row(_: &PgRow) -> Result<Self::Data, sqlx::Error> {
+}
+
+impl<DB: Backend> Decoder<DB> for () {
+    fn from_row(&self, _: &DB::Row) -> Result<Self::Data, sqlx::Error> {
         Ok(())
     }
 }
@@ -57,12 +170,30 @@ macro_rules! impl_selector {
             where $($t: Selector),*
         {
             type Data = ($($t::Data),*);
-            fn cols() -> impl Iterator<Item = Col> {
-                std::iter::empty()$(.chain($t::cols()))*
+            #[allow(non_snake_case)]
+            fn cols(&self) -> impl Iterator<Item = Col> {
+                let ($($t),*) = self;
+                std::iter::empty()$(.chain($t.cols()))*
+            }
+        }
+
+        impl<DB: Backend, $($t),*> Decoder<DB> for ($($t),*)
+            where $($t: Decoder<DB>),*
+        {
+            #[allow(non_snake_case)]
+            fn from_row(&self, row: &DB::Row) -> Result<Self::Data, sqlx::Error> {
+                let ($($t),*) = self;
+                Ok(($($t.from_row(row)?),*))
             }
+        }
 
-            fn from_row(row: &PgRow) -> Result<Self::Data, sqlx::Error> {
-                Ok(($($t::from_row(row)?),*))
+        impl<'q, DB: Backend, $($t),*> Keyset<'q, DB> for ($($t),*)
+            where $($t: Keyset<'q, DB>),*
+        {
+            #[allow(non_snake_case)]
+            fn push_values(data: Self::Data, sep: &mut Separated<'_, 'q, DB, &'static str>) {
+                let ($($t),*) = data;
+                $($t::push_values($t, sep);)*
             }
         }
     };
@@ -80,6 +211,107 @@ macro_rules! impl_selector_for_tuples {
 
 impl_selector_for_tuples!(A, B, C, D, E, F, G, H, I, J, K, L);
 
+/// Selects `S` from an outer join, decoding to `None` when the join found no
+/// row, i.e. when every column of `S` (including its primary key) is `NULL`.
+pub struct Nullable<S>(pub S);
+
+impl<S: Selector> Selector for Nullable<S> {
+    type Data = Option<S::Data>;
+    fn cols(&self) -> impl Iterator<Item = Col> {
+        self.0.cols()
+    }
+}
+
+impl<DB: Backend, S: Decoder<DB>> Decoder<DB> for Nullable<S> {
+    fn from_row(&self, row: &DB::Row) -> Result<Self::Data, sqlx::Error> {
+        for col in self.0.cols() {
+            if !DB::is_null(row, col.alias().as_str())? {
+                return self.0.from_row(row).map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// An entity under a table alias, see `EntityTrait::alias`. Selecting it
+/// selects the whole entity from the alias.
+#[derive(Debug)]
+pub struct TableAlias<E> {
+    name: Iden,
+    _pha: PhantomData<E>,
+}
+
+impl<E> Clone for TableAlias<E> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            _pha: PhantomData,
+        }
+    }
+}
+
+impl<E: EntityTrait> TableAlias<E> {
+    pub fn name(&self) -> &Iden {
+        &self.name
+    }
+
+    /// Re-binds a column of `E` to this alias.
+    pub fn col<C>(&self, col: C) -> AliasedCol<C>
+    where
+        C: Column<Entity = E> + Into<Col>,
+    {
+        let col: Col = col.into();
+        AliasedCol {
+            col: Col::new(self.name.clone(), col.col),
+            _pha: PhantomData,
+        }
+    }
+}
+
+impl<E: EntityTrait> Selector for TableAlias<E> {
+    type Data = E::Row;
+    fn cols(&self) -> impl Iterator<Item = Col> {
+        E::columns(&self.name).into_iter()
+    }
+}
+
+impl<DB: Backend, E: DecodeEntity<DB>> Decoder<DB> for TableAlias<E> {
+    fn from_row(&self, row: &DB::Row) -> Result<Self::Data, sqlx::Error> {
+        E::decode(row, &self.name)
+    }
+}
+
+/// A column marker `C` re-bound to a table alias.
+#[derive(Debug)]
+pub struct AliasedCol<C> {
+    col: Col,
+    _pha: PhantomData<C>,
+}
+
+impl<C> From<AliasedCol<C>> for Col {
+    fn from(val: AliasedCol<C>) -> Self {
+        val.col
+    }
+}
+
+impl<C: Selector> Selector for AliasedCol<C> {
+    type Data = C::Data;
+    fn cols(&self) -> impl Iterator<Item = Col> {
+        std::iter::once(self.col.clone())
+    }
+}
+
+impl<DB, C> Decoder<DB> for AliasedCol<C>
+where
+    DB: Backend,
+    C: Selector,
+    C::Data: for<'r> Decode<'r, DB> + Type<DB>,
+{
+    fn from_row(&self, row: &DB::Row) -> Result<Self::Data, sqlx::Error> {
+        DB::try_get(row, self.col.alias().as_str())
+    }
+}
+
 pub trait ColumnList {
     type Extractor: for<'r> FromRow<'r, PgRow>;
     type Extracted: From<Self::Extractor>;
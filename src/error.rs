@@ -0,0 +1,86 @@
+use std::fmt::{Display, Formatter};
+
+/// Errors of the methods that can fail before a query reaches the database,
+/// because the query was misused. Database and decoding errors are passed
+/// through as `Error::Sqlx`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Sqlx(sqlx::Error),
+    /// A name rejected by `Iden::new`.
+    InvalidIdentifier {
+        iden: String,
+        problem: &'static str,
+    },
+    /// A page size below 1, or too large to fetch one row past it, given to
+    /// `Select::paginate_after`.
+    InvalidPageSize(i64),
+    /// An upsert's `do_update` or `do_update_set` without any column to
+    /// update.
+    EmptyConflictUpdate,
+    /// An upsert's `do_update` or `do_update_set` on `on_conflict(())`,
+    /// which names no column to detect conflicts on.
+    EmptyConflictTarget,
+    /// A relation given to `Select::join_as` that doesn't come from the
+    /// aliased table, or to `find_related_via` or `find_related_via_as`
+    /// that doesn't involve the entity.
+    RelationMismatch {
+        from: String,
+        table: String,
+    },
+    /// A relation of a table to itself given to `find_related_via`, which
+    /// can only join it under an alias with `find_related_via_as`.
+    SelfRelation {
+        table: String,
+    },
+    /// A `Delete` with neither a filter nor `delete_all`.
+    MissingFilter {
+        table: String,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Sqlx(e) => e.fmt(f),
+            Error::InvalidIdentifier { iden, problem } => {
+                write!(f, "identifier {:?} {}", iden, problem)
+            }
+            Error::InvalidPageSize(size) => write!(f, "invalid page size {}", size),
+            Error::EmptyConflictUpdate => {
+                f.write_str("ON CONFLICT DO UPDATE has no column to update, use `do_nothing`")
+            }
+            Error::EmptyConflictTarget => {
+                f.write_str("ON CONFLICT DO UPDATE needs a conflict target")
+            }
+            Error::RelationMismatch { from, table } => {
+                write!(f, "relation from {} can't be joined as {}", from, table)
+            }
+            Error::SelfRelation { table } => write!(
+                f,
+                "relation of {} to itself needs an alias, use `find_related_via_as`",
+                table
+            ),
+            Error::MissingFilter { table } => write!(
+                f,
+                "refusing to delete from {} without a filter, use `delete_all`",
+                table
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Sqlx(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        Error::Sqlx(e)
+    }
+}
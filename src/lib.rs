@@ -1,5 +1,13 @@
+extern crate self as easy_orm;
+
+pub mod aggregate;
+pub mod backend;
 pub mod common;
+pub mod error;
 #[macro_use]
 mod macros;
 pub mod relations;
 pub mod sql;
+pub mod transaction;
+
+pub use easy_orm_derive::Entity;
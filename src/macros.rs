@@ -2,6 +2,7 @@
 macro_rules! def_cols {
     ($c:ident : $c_ty: ty) => {
         paste::paste! {
+            #[derive(Default, Clone, Copy)]
             pub struct [<$c:camel>];
             #[derive(FromRow)]
             pub struct [<$c:camel Extractor>] {
@@ -9,77 +10,233 @@ macro_rules! def_cols {
             }
 
             impl From<[<$c:camel>]> for Col {
-                fn from(val: [<$c:camel>]) -> Self {
+                fn from(_: [<$c:camel>]) -> Self {
                     Col::new(
-                        Entity::TABLE_NAME.into(), stringify!($c).into()
+                        Entity::TABLE_NAME.into(), $crate::sql::ident_name(stringify!($c)).into()
                     )
                 }
             }
 
             impl $crate::common::Selector for [<$c:camel>] {
                 type Data = $c_ty;
-                fn cols() -> impl Iterator<Item = Col> {
+                fn cols(&self) -> impl Iterator<Item = Col> {
                     std::iter::once(Col::new(
-                        Entity::TABLE_NAME.into(), stringify!($c).into()
+                        Entity::TABLE_NAME.into(), $crate::sql::ident_name(stringify!($c)).into()
                     ))
                 }
-                fn from_row(row: &sqlx::postgres::PgRow) -> Result<Self::Data, sqlx::Error> {
-                    use sqlx::Row;
-                    row.try_get(stringify!($c))
+            }
+
+            impl $crate::common::Column for [<$c:camel>] {
+                type Entity = Entity;
+            }
+
+            impl<DB: Backend> $crate::common::Decoder<DB> for [<$c:camel>]
+            where
+                $c_ty: SqlType<DB>,
+            {
+                fn from_row(&self, row: &DB::Row) -> Result<Self::Data, sqlx::Error> {
+                    let col: Col = [<$c:camel>].into();
+                    DB::try_get(row, col.alias().as_str())
+                }
+            }
+
+            impl<'q, DB: Backend> $crate::common::Keyset<'q, DB> for [<$c:camel>]
+            where
+                $c_ty: SqlType<DB>,
+            {
+                fn push_values(
+                    data: Self::Data,
+                    sep: &mut sqlx::query_builder::Separated<'_, 'q, DB, &'static str>,
+                ) {
+                    sep.push_bind(data);
+                }
+            }
+        }
+    };
+}
+
+/// Generates `Relation` for the foreign keys of a `data_table!`, given as
+/// `(col ref ref_col [name?])`. A relation is named after the referenced
+/// entity unless given a name with `as`, and only unnamed relations get
+/// `Related` impls, so several relations to the same entity don't conflict.
+/// Named relations are therefore joined with `find_related_via` and can't
+/// be used with `find_related` or `Select::load_related`.
+#[macro_export]
+macro_rules! def_relations {
+    ([$($done:tt)*] ($col:ident $ref:ident $ref_col:ident [$rel:ident]) $($rest:tt)*) => {
+        $crate::def_relations!([$($done)* ($col $ref $ref_col $rel named)] $($rest)*);
+    };
+    ([$($done:tt)*] ($col:ident $ref:ident $ref_col:ident []) $($rest:tt)*) => {
+        paste::paste! {
+            $crate::def_relations!(
+                [$($done)* ($col $ref $ref_col [<$ref:camel>] unnamed)] $($rest)*
+            );
+        }
+    };
+    ([$(($col:ident $ref:ident $ref_col:ident $rel:ident $named:ident))*]) => {
+        paste::paste! {
+            pub enum Relation {
+                $($rel,)*
+                Nothing
+            }
+
+            impl RelationTrait for Relation {
+                fn def(&self) -> RelationDef {
+                    match self {
+                        $(
+                        Relation::$rel => RelationBuilder::new()
+                            .from(Entity::[<$col:camel>])
+                            .to([<$ref:snake>]::Entity::[<$ref_col:camel>])
+                            .into(),
+                        )*
+                        Relation::Nothing => unreachable!()
+                    }
+                }
+            }
+
+            $($crate::def_relations!(@related $named $col $ref $ref_col $rel);)*
+        }
+    };
+    (@related named $($_:tt)*) => {};
+    (@related unnamed $col:ident $ref:ident $ref_col:ident $rel:ident) => {
+        paste::paste! {
+            impl Related<[<$ref:snake>]::Entity> for Entity {
+                type Key = cols::[<$col:camel>];
+                fn to() -> RelationDef {
+                    Relation::$rel.def()
+                }
+            }
+
+            impl Related<Entity> for [<$ref:snake>]::Entity {
+                type Key = [<$ref:snake>]::cols::[<$ref_col:camel>];
+                fn to() -> RelationDef {
+                    Relation::$rel.def().rev()
                 }
             }
         }
     };
 }
 
+/// Generates `set_null_<col>` for the columns declared as `Option<_>`. Takes
+/// the columns of a `data_table!` as plain tokens, since a type that was
+/// already matched as `$t:ty` can't be taken apart anymore.
+#[macro_export]
+macro_rules! def_set_null {
+    ([$id_col:ident: $id_ty:ty] $(, $($rest:tt)*)?) => {
+        $crate::def_set_null!($($($rest)*)?);
+    };
+    ($col:ident : Option<$inner:ty> $(= $default:ident)? $(=> $ref:ident.$ref_col:ident $(as $rel:ident)?)? $(, $($rest:tt)*)?) => {
+        paste::paste! {
+            /// Explicitly sets the column to `NULL`.
+            pub fn [<set_null_ $col>](mut self) -> Self {
+                self.$col = Some(None);
+                self
+            }
+        }
+        $crate::def_set_null!($($($rest)*)?);
+    };
+    ($col:ident : $col_ty:ty $(= $default:ident)? $(=> $ref:ident.$ref_col:ident $(as $rel:ident)?)? $(, $($rest:tt)*)?) => {
+        $crate::def_set_null!($($($rest)*)?);
+    };
+    () => {};
+}
+
+/// The name of a `data_table!`'s table, which is given either as an
+/// identifier or, e.g. for names that are keywords, as a string literal.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_name {
+    ($table:ident) => {
+        $crate::sql::ident_name(stringify!($table))
+    };
+    ($table:literal) => {
+        $table
+    };
+}
+
 #[macro_export]
 macro_rules! data_table {
-    ($model:ident of $table_name:ident {
+    ($model:ident of $table_name:tt { $($cols:tt)* }) => {
+        $crate::data_table!(@row $model of $table_name [$($cols)*] { $($cols)* });
+    };
+    (@row $model:ident of $table_name:tt [$($cols:tt)*] {
+        $([$id_col:ident: $id_ty:ty],)?
+        $($col:ident : $col_ty:ty $(= $default:ident)? $(=> $ref:ident.$ref_col:ident $(as $rel:ident)?)?),* $(,)?
+    }) => {
+        $crate::data_table!(@items $model as $model of $table_name [
+            #[derive(Debug, Clone, FromRow)]
+            pub struct Row {
+                $(pub $id_col: $id_ty,)?
+                $(pub $col: $col_ty,)*
+            }
+        ] [$($cols)*] {
+            $([$id_col: $id_ty],)?
+            $($col: $col_ty $(= $default)? $(=> $ref.$ref_col $(as $rel)?)?),*
+        });
+    };
+    // Shared with `#[derive(Entity)]`, which brings its own `Row` and
+    // exports the entity as `{Model}Entity`. The columns are given twice,
+    // once as plain tokens for `def_set_null!`.
+    (@items $model:ident as $entity:ident of $table_name:tt [$($row:tt)*] [$($cols:tt)*] {
         $([$id_col:ident: $id_ty:ty],)?
-        $($col:ident : $col_ty:ty $(=> $ref:ident.$ref_col:ident)?),* $(,)?
+        $($col:ident : $col_ty:ty $(= $default:ident)? $(=> $ref:ident.$ref_col:ident $(as $rel:ident)?)?),* $(,)?
     }) => {
         paste::paste!{
         #[allow(unused_imports)]
         pub use [<$model:snake>]::{
-            Entity as $model,
+            Entity as $entity,
             Insert as [<Insert $model>],
-            Update as [<Update $model>]
+            Update as [<Update $model>],
+            Delete as [<Delete $model>]
         };
         pub mod [<$model:snake>] {
             #![allow(unused_imports)]
             #![allow(dead_code)]
             use super::*;
-            use $crate::common::{EntityTrait, ColumnList};
+            use $crate::backend::{Backend, InsertId, SqlType, SupportsReturning};
+            use $crate::common::{EntityTrait, ColumnList, DecodeEntity, Decoder, Selector};
             use $crate::relations::{ RelationTrait, RelationDef, RelationBuilder, Related };
-            use $crate::sql::{Col, Select};
-            use sqlx::{ QueryBuilder, Postgres, PgExecutor, Error, FromRow };
-
-            macro_rules! idem_option {
-                (Option<$ty:ty>) => {
-                    Option<$ty>
-                };
-                ($ty:ty) => {
-                    Option<$ty>
-                }
-            }
+            use $crate::sql::{push_returning, And, Col, ColEq, ConflictAction, Iden, IntoCol, Select, ToSql};
+            use sqlx::{ QueryBuilder, Executor, Error, FromRow };
 
+            #[derive(Default, Clone, Copy)]
             pub struct Entity;
 
             impl EntityTrait for Entity {
                 type Row = Row;
-                const TABLE_NAME: &'static str = stringify!($table_name);
+                const TABLE_NAME: &'static str = $crate::table_name!($table_name);
+
+                fn columns(tbl: &Iden) -> Vec<Col> {
+                    vec![
+                        $(Col::new(tbl.clone(), $crate::sql::ident_name(stringify!($id_col)).into()),)?
+                        $(Col::new(tbl.clone(), $crate::sql::ident_name(stringify!($col)).into()),)*
+                    ]
+                }
+
             }
 
-            #[derive(Debug, FromRow)]
-            pub struct Row {
-                $(pub $id_col: $id_ty,)?
-                $(pub $col: $col_ty,)*
+            impl<DB: Backend> DecodeEntity<DB> for Entity
+            where
+                $($id_ty: SqlType<DB>,)?
+                $($col_ty: SqlType<DB>,)*
+            {
+                fn decode(row: &DB::Row, tbl: &Iden) -> Result<Row, Error> {
+                    let alias = |col: &str| Col::new(tbl.clone(), col.into()).alias();
+                    Ok(Row {
+                        $($id_col: DB::try_get(row, alias($crate::sql::ident_name(stringify!($id_col))).as_str())?,)?
+                        $($col: DB::try_get(row, alias($crate::sql::ident_name(stringify!($col))).as_str())?,)*
+                    })
+                }
             }
 
+            $($row)*
+
+            /// Each column is `None` until set, and only set columns are
+            /// written by the query.
             #[derive(Debug, Default)]
             pub struct Update {
                 $($id_col: $id_ty,)?
-                $($col: idem_option!($col_ty),)*
+                $($col: Option<$col_ty>,)*
             }
 
             impl Update {
@@ -97,33 +254,136 @@ macro_rules! data_table {
                 }
 
                 $(
+                #[allow(clippy::wrong_self_convention)]
                 pub fn $col(mut self, val: impl Into<$col_ty>) -> Self
                 {
-                    let val: $col_ty = val.into();
-                    self.$col = val.into();
+                    self.$col = Some(val.into());
                     self
                 }
                 )*
 
-                pub fn query(&self) -> QueryBuilder<Postgres> {
-                    let sql = format!("UPDATE {} SET ", Entity::TABLE_NAME);
-                    let mut builder = QueryBuilder::<Postgres>::new(sql);
+                $crate::def_set_null!($($cols)*);
+
+                pub fn is_empty(&self) -> bool {
+                    true $(&& self.$col.is_none())*
+                }
+
+                /// Returns `None` if no column has been set, since there is
+                /// nothing to update.
+                pub fn query<DB: Backend>(&self) -> Option<QueryBuilder<'_, DB>>
+                where
+                    $($id_ty: SqlType<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
+                    if self.is_empty() {
+                        return None;
+                    }
+                    let sql = format!("UPDATE {} SET ", Iden::from(Entity::TABLE_NAME).sql::<DB>());
+                    let mut builder = QueryBuilder::new(sql);
+                    self.push_set(&mut builder);
+                    self.push_where(&mut builder);
+                    Some(builder)
+                }
+
+                fn push_set<'q, DB: Backend>(&'q self, builder: &mut QueryBuilder<'q, DB>)
+                where
+                    $($id_ty: SqlType<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
                     let mut sep = builder.separated(", ");
                     $(
-                        sep.push(format!("{} = ", stringify!($col)));
-                        sep.push_bind_unseparated(&self.$col);
+                        if let Some(val) = &self.$col {
+                            sep.push(format!("{} = ", Iden::from($crate::sql::ident_name(stringify!($col))).sql::<DB>()));
+                            sep.push_bind_unseparated(val);
+                        }
                     )*
+                }
+
+                #[allow(unused_variables)]
+                fn push_where<'q, DB: Backend>(&'q self, builder: &mut QueryBuilder<'q, DB>)
+                where
+                    $($id_ty: SqlType<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
                     $(
-                        builder.push(format!(" WHERE {} = ", stringify!($id_col)));
-                        builder.push_bind(self.$id_col);
+                        builder.push(format!(" WHERE {} = ", Iden::from($crate::sql::ident_name(stringify!($id_col))).sql::<DB>()));
+                        builder.push_bind(&self.$id_col);
                     )?
-                    builder
                 }
+
+                /// Runs the update, returning the number of rows affected.
+                pub async fn exec<'c, DB, E>(&self, e: E) -> Result<u64, Error>
+                where
+                    DB: Backend,
+                    E: Executor<'c, Database = DB>,
+                    $($id_ty: SqlType<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
+                    match self.query() {
+                        Some(mut query) => DB::execute(&mut query, e).await,
+                        None => Ok(0),
+                    }
+                }
+
+                /// Runs the update and returns the updated row. An empty
+                /// update just fetches the row as it is.
+                pub async fn returning<'c, DB, E>(&self, e: E) -> Result<Row, Error>
+                where
+                    DB: SupportsReturning,
+                    E: Executor<'c, Database = DB>,
+                    $($id_ty: SqlType<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
+                    let query = match self.query() {
+                        Some(mut query) => {
+                            push_returning(&mut query, Entity.cols());
+                            query
+                        }
+                        None => {
+                            let mut builder = QueryBuilder::new(Entity::find().sql::<DB>().to_string());
+                            self.push_where(&mut builder);
+                            builder
+                        }
+                    };
+                    $crate::backend::fetch_one(query, e, |row| Decoder::<DB>::from_row(&Entity, row)).await
+                }
+            }
+
+            pub type Delete<F = ()> = $crate::sql::Delete<Entity, F>;
+
+            $(
+            impl Entity {
+                pub fn delete_by_id($id_col: $id_ty) -> Delete<And<(), ColEq<$id_ty>>> {
+                    Entity::delete().filter(Entity::[<$id_col:camel>].eq($id_col))
+                }
+            }
+            )?
+
+            // Columns declared with `= default` are optional in `Insert`,
+            // and left to the database default when unset.
+            macro_rules! insert_ty {
+                ($ty:ty, default) => { Option<$ty> };
+                ($ty:ty) => { $ty };
+            }
+
+            macro_rules! insert_is_set {
+                ($val:expr, default) => { $val.is_some() };
+                ($val:expr) => { true };
+            }
+
+            macro_rules! insert_bind {
+                ($sep:ident, $val:expr, default) => {
+                    match &$val {
+                        Some(val) => $sep.push_bind(val),
+                        None => $sep.push("DEFAULT"),
+                    }
+                };
+                ($sep:ident, $val:expr) => { $sep.push_bind(&$val) };
             }
 
             #[derive(Debug, Default)]
             pub struct Insert {
-                $(pub $col: $col_ty,)*
+                $(pub $col: insert_ty!($col_ty $(, $default)?),)*
             }
 
             macro_rules! cond {
@@ -135,23 +395,270 @@ macro_rules! data_table {
             type IdTy = cond!( $($id_col;)? then $($id_ty)?; else ());
 
             impl Insert {
-                pub fn insert_query(&self) -> QueryBuilder<Postgres> {
-                    let cols = stringify!($($col),*);
-                    let sql = format!("INSERT INTO {} ({}) VALUES (", Entity::TABLE_NAME, cols);
-                    let mut builder = QueryBuilder::<Postgres>::new(sql);
+                /// Whether no `= default` column is left unset.
+                fn all_set(&self) -> bool {
+                    true $(&& insert_is_set!(self.$col $(, $default)?))*
+                }
+
+                /// Only lists the columns that are set, so unset `= default`
+                /// columns get their database default.
+                pub fn insert_query<DB: Backend>(&self) -> QueryBuilder<'_, DB>
+                where
+                    $($col_ty: SqlType<DB>,)*
+                {
+                    let mut cols = Vec::new();
+                    $(
+                        if insert_is_set!(self.$col $(, $default)?) {
+                            cols.push(Iden::from($crate::sql::ident_name(stringify!($col))).sql::<DB>().to_string());
+                        }
+                    )*
+                    if cols.is_empty() {
+                        let sql = format!("INSERT INTO {} {}", Iden::from(Entity::TABLE_NAME).sql::<DB>(), DB::DEFAULT_VALUES);
+                        return QueryBuilder::new(sql);
+                    }
+
+                    let sql = format!("INSERT INTO {} ({}) VALUES (", Iden::from(Entity::TABLE_NAME).sql::<DB>(), cols.join(", "));
+                    let mut builder = QueryBuilder::new(sql);
                     let mut sep = builder.separated(", ");
-                    $(sep.push_bind(&self.$col);)*
+                    $(
+                        if insert_is_set!(self.$col $(, $default)?) {
+                            insert_bind!(sep, self.$col $(, $default)?);
+                        }
+                    )*
                     sep.push_unseparated(")");
                     builder
                 }
 
-                pub async fn insert<'c, E: PgExecutor<'c>>(&self, e: E) -> Result<IdTy, Error>{
+                pub async fn insert<'c, DB, E>(&self, e: E) -> Result<IdTy, Error>
+                where
+                    DB: Backend,
+                    E: Executor<'c, Database = DB>,
+                    $($id_ty: SqlType<DB> + InsertId<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
+                    let mut query = self.insert_query();
+                    cond!($($id_col;)? then $({
+                        let col = Entity::[<$id_col:camel>].into();
+                        let id = <$id_ty as InsertId<DB>>::insert_id(&mut query, col, e).await?;
+                        id.ok_or(Error::RowNotFound)
+                    })?; else {
+                        DB::execute(&mut query, e).await.map(|_| ())
+                    })
+                }
+
+                /// Inserts the row and returns it as stored, including the
+                /// columns filled in by the database.
+                pub async fn insert_returning<'c, DB, E>(&self, e: E) -> Result<Row, Error>
+                where
+                    DB: SupportsReturning,
+                    E: Executor<'c, Database = DB>,
+                    $($id_ty: SqlType<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
                     let mut query = self.insert_query();
-                    $(query.push(format!(" RETURNING {}", stringify!($id_col)));)?
-                    query.build_query_scalar().fetch_one(e).await
+                    push_returning(&mut query, Entity.cols());
+                    $crate::backend::fetch_one(query, e, |row| Decoder::<DB>::from_row(&Entity, row)).await
+                }
+
+                /// Starts an `INSERT ... ON CONFLICT` on the `target` columns,
+                /// e.g. `(Entity::ExternalIdentityProvider, Entity::ExternalIdentityNumber)`.
+                /// Without columns, i.e. `()`, any conflict is caught, which
+                /// only `do_nothing` accepts.
+                /// MySQL has no `ON CONFLICT` and takes a conflict on any
+                /// unique key instead, with `ON DUPLICATE KEY UPDATE`.
+                pub fn on_conflict<K: Selector>(&self, target: K) -> OnConflict<'_> {
+                    OnConflict {
+                        insert: self,
+                        target: target.cols().collect(),
+                    }
+                }
+
+                /// Inserts all `rows` with multi-row `VALUES` statements,
+                /// chunked to stay under the bind parameter limit, and
+                /// returns their ids in order. The chunks run in one
+                /// transaction, so either every row is inserted or none.
+                /// Backends that can't write `DEFAULT` in a `VALUES` list
+                /// insert the rows one by one instead if any of them leaves
+                /// a `= default` column unset.
+                pub async fn insert_many<'c, DB, A>(
+                    rows: impl IntoIterator<Item = Insert>,
+                    conn: A,
+                ) -> Result<Vec<IdTy>, Error>
+                where
+                    DB: Backend,
+                    A: sqlx::Acquire<'c, Database = DB>,
+                    $($id_ty: SqlType<DB> + InsertId<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
+                    let rows: Vec<Insert> = rows.into_iter().collect();
+                    let cols = [$(Iden::from($crate::sql::ident_name(stringify!($col))).sql::<DB>().to_string()),*];
+                    let chunk_size = DB::BIND_LIMIT / cols.len().max(1);
+
+                    let mut tx = conn.begin().await?;
+                    let mut ids = Vec::with_capacity(rows.len());
+                    if DB::DEFAULT_IN_VALUES || rows.iter().all(Insert::all_set) {
+                        for chunk in rows.chunks(chunk_size) {
+                            let sql = format!("INSERT INTO {} ({}) ", Iden::from(Entity::TABLE_NAME).sql::<DB>(), cols.join(", "));
+                            let mut builder = QueryBuilder::new(sql);
+                            builder.push_values(chunk, |mut sep, row| {
+                                $(insert_bind!(sep, row.$col $(, $default)?);)*
+                            });
+                            cond!($($id_col;)? then $({
+                                let col = Entity::[<$id_col:camel>].into();
+                                let chunk_ids = <$id_ty as InsertId<DB>>::insert_ids(builder, col, chunk.len(), DB::executor(&mut tx)).await?;
+                                ids.extend(chunk_ids);
+                            })?; else {
+                                DB::execute(&mut builder, DB::executor(&mut tx)).await?;
+                                ids.extend(chunk.iter().map(|_| ()));
+                            });
+                        }
+                    } else {
+                        for row in &rows {
+                            ids.push(row.insert(DB::executor(&mut tx)).await?);
+                        }
+                    }
+                    tx.commit().await?;
+                    Ok(ids)
+                }
+            }
+
+            pub struct OnConflict<'a> {
+                insert: &'a Insert,
+                target: Vec<Col>,
+            }
+
+            impl<'a> OnConflict<'a> {
+                pub fn do_nothing(self) -> Upsert<'a> {
+                    self.action(ConflictAction::Nothing)
+                }
+
+                /// Overwrites the given columns with the values that were
+                /// being inserted. The upsert fails with
+                /// `Error::EmptyConflictUpdate` if `cols` is empty.
+                pub fn do_update<K: Selector>(self, cols: K) -> Upsert<'a> {
+                    self.action(ConflictAction::Excluded(cols.cols().collect()))
+                }
+
+                /// Applies the columns set on `update` to the conflicting row.
+                /// The upsert fails with `Error::EmptyConflictUpdate` if no
+                /// column is set.
+                pub fn do_update_set(self, update: Update) -> Upsert<'a> {
+                    self.action(ConflictAction::Set(update))
+                }
+
+                fn action(self, action: ConflictAction<Update>) -> Upsert<'a> {
+                    Upsert {
+                        insert: self.insert,
+                        target: self.target,
+                        action,
+                    }
                 }
             }
 
+            pub struct Upsert<'a> {
+                insert: &'a Insert,
+                target: Vec<Col>,
+                action: ConflictAction<Update>,
+            }
+
+            impl Upsert<'_> {
+                pub fn query<DB: Backend>(&self) -> Result<QueryBuilder<'_, DB>, $crate::error::Error>
+                where
+                    $($id_ty: SqlType<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
+                    if self.target.is_empty() && !matches!(self.action, ConflictAction::Nothing) {
+                        return Err($crate::error::Error::EmptyConflictTarget);
+                    }
+                    let mut builder = self.insert.insert_query();
+                    if DB::ON_DUPLICATE_KEY {
+                        self.push_on_duplicate_key(&mut builder)?;
+                        return Ok(builder);
+                    }
+                    if self.target.is_empty() {
+                        builder.push(" ON CONFLICT DO ");
+                    } else {
+                        let target: Vec<_> = self.target.iter().map(|c| c.col.sql::<DB>().to_string()).collect();
+                        builder.push(format!(" ON CONFLICT ({}) DO ", target.join(", ")));
+                    }
+                    match &self.action {
+                        ConflictAction::Nothing => {
+                            builder.push("NOTHING");
+                        }
+                        ConflictAction::Excluded(cols) if !cols.is_empty() => {
+                            let sets: Vec<_> = cols
+                                .iter()
+                                .map(|c| format!("{0} = EXCLUDED.{0}", c.col.sql::<DB>()))
+                                .collect();
+                            builder.push(format!("UPDATE SET {}", sets.join(", ")));
+                        }
+                        ConflictAction::Set(update) if !update.is_empty() => {
+                            builder.push("UPDATE SET ");
+                            update.push_set(&mut builder);
+                        }
+                        _ => return Err($crate::error::Error::EmptyConflictUpdate),
+                    }
+                    Ok(builder)
+                }
+
+                // `do_nothing` assigns the first column of the table to
+                // itself. Updates also assign the id to `LAST_INSERT_ID(id)`,
+                // so the id of the updated row is reported like that of an
+                // insert.
+                fn push_on_duplicate_key<'q, DB: Backend>(
+                    &'q self,
+                    builder: &mut QueryBuilder<'q, DB>,
+                ) -> Result<(), $crate::error::Error>
+                where
+                    $($id_ty: SqlType<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
+                    builder.push(" ON DUPLICATE KEY UPDATE ");
+                    let report_id: Vec<String> = vec![$(
+                        format!("{0} = LAST_INSERT_ID({0})", Iden::from($crate::sql::ident_name(stringify!($id_col))).sql::<DB>())
+                    )?];
+                    match &self.action {
+                        ConflictAction::Nothing => {
+                            let cols = Entity::columns(&Entity::TABLE_NAME.into());
+                            let col = cols.first().expect("a table has columns");
+                            builder.push(format!("{0} = {0}", col.col.sql::<DB>()));
+                        }
+                        ConflictAction::Excluded(cols) if !cols.is_empty() => {
+                            let sets: Vec<_> = report_id
+                                .into_iter()
+                                .chain(cols.iter().map(|c| format!("{0} = VALUES({0})", c.col.sql::<DB>())))
+                                .collect();
+                            builder.push(sets.join(", "));
+                        }
+                        ConflictAction::Set(update) if !update.is_empty() => {
+                            for set in report_id {
+                                builder.push(format!("{}, ", set));
+                            }
+                            update.push_set(builder);
+                        }
+                        _ => return Err($crate::error::Error::EmptyConflictUpdate),
+                    }
+                    Ok(())
+                }
+
+                /// Returns `None` if the conflicting row was left untouched.
+                pub async fn insert<'c, DB, E>(&self, e: E) -> Result<Option<IdTy>, $crate::error::Error>
+                where
+                    DB: Backend,
+                    E: Executor<'c, Database = DB>,
+                    $($id_ty: SqlType<DB> + InsertId<DB>,)?
+                    $($col_ty: SqlType<DB>,)*
+                {
+                    let mut query = self.query()?;
+                    cond!($($id_col;)? then $({
+                        let col = Entity::[<$id_col:camel>].into();
+                        Ok(<$id_ty as InsertId<DB>>::insert_id(&mut query, col, e).await?)
+                    })?; else {
+                        let affected = DB::execute(&mut query, e).await?;
+                        Ok((affected > 0).then_some(()))
+                    })
+                }
+            }
 
             pub mod cols {
                 use super::*;
@@ -166,49 +673,24 @@ macro_rules! data_table {
 
                 impl $crate::common::Selector for Entity {
                     type Data = Row;
-                    fn cols() -> impl Iterator<Item = Col> {
-                        std::iter::once(Col::new(
-                            Self::TABLE_NAME.into(), "*".into()
-                        ))
-                    }
-                    fn from_row(row: &sqlx::postgres::PgRow) -> Result<Self::Data, sqlx::Error> {
-                        Row::from_row(row)
+                    fn cols(&self) -> impl Iterator<Item = Col> {
+                        Entity::columns(&Entity::TABLE_NAME.into()).into_iter()
                     }
                 }
-            }
 
-            pub enum Relation {
-                $($([<$ref:camel>],)?)*
-                Nothing
-            }
-
-            impl RelationTrait for Relation {
-                fn def(&self) -> RelationDef {
-                    match self {
-                        $($(
-                        Relation::[<$ref:camel>] => RelationBuilder::new()
-                            .from(Entity::[<$col:camel>])
-                            .to([<$ref:snake>]::Entity::[<$ref_col:camel>])
-                            .into(),
-                        )?)*
-                        Relation::Nothing => unreachable!()
+                impl<DB: Backend> $crate::common::Decoder<DB> for Entity
+                where
+                    Entity: DecodeEntity<DB>,
+                {
+                    fn from_row(&self, row: &DB::Row) -> Result<Self::Data, sqlx::Error> {
+                        Entity::decode(row, &Entity::TABLE_NAME.into())
                     }
                 }
             }
 
-            $($(
-            impl Related<[<$ref:snake>]::Entity> for Entity {
-                fn to() -> RelationDef {
-                    Relation::[<$ref:camel>].def()
-                }
-            }
-
-            impl Related<Entity> for [<$ref:snake>]::Entity {
-                fn to() -> RelationDef {
-                    Relation::[<$ref:camel>].def().rev()
-                }
-            }
-            )?)*
+            $crate::def_relations!(
+                [] $($(($col $ref $ref_col [$($rel)?]))?)*
+            );
         }
         }
     };
@@ -219,6 +701,7 @@ macro_rules! many_to_many {
     ($from:ident -> $via:ident -> $to:ident) => {
         paste::paste! {
         impl $crate::relations::Related<$to::Entity> for $from::Entity {
+            type Key = <$from::Entity as $crate::relations::Related<$via::Entity>>::Key;
             fn to() -> $crate::relations::RelationDef {
                 use $crate::relations::RelationTrait;
                 $via::Relation::[<$to:camel>].def()
@@ -239,7 +722,13 @@ macro_rules! many_to_many {
 
 #[cfg(test)]
 mod tests {
-    use crate::common::EntityTrait;
+    use crate::{
+        aggregate::{avg, count, count_distinct, count_star, max, sum},
+        common::EntityTrait,
+        relations::RelationTrait,
+        sql::{Col, Filter, Iden, IntoCol, JoinTy, KeysetAfter, Nulls, Order},
+    };
+    use sqlx::Postgres;
 
     data_table!(Person of people {
         [id: i32],
@@ -251,6 +740,21 @@ mod tests {
     data_table!(Circle of circles {
         [id: i32],
         name: String,
+        is_connected: bool = default,
+    });
+
+    // Shaped like the `circles` of the demo.
+    data_table!(Team of teams {
+        [id: i32],
+        name: String,
+        super_team_id: Option<i32> => Team.id as SuperTeam,
+        admin_team_id: Option<i32> => Team.id as AdminTeam,
+    });
+
+    data_table!(Category of categories {
+        [id: i32],
+        name: String,
+        parent_id: Option<i32> => Category.id as Parent,
     });
 
     data_table!(PersonCircle of person_circle {
@@ -258,26 +762,893 @@ mod tests {
         circle_id: i32 => Circle.id,
     });
 
+    data_table!(Invite of invites {
+        [id: i32],
+        inviter_id: i32 => Person.id as Inviter,
+        invitee_id: i32 => Person.id as Invitee,
+        circle_id: i32 => Circle.id,
+    });
+
     many_to_many!(person - person_circle - circle);
 
+    #[allow(dead_code)]
+    #[derive(Debug, Clone, crate::Entity)]
+    #[orm(table = "members")]
+    pub struct Member {
+        #[orm(primary_key)]
+        pub id: i32,
+        pub nickname: String,
+        #[orm(default)]
+        pub is_active: bool,
+        #[orm(references = "Circle.id", relation = "HomeCircle")]
+        pub circle_id: Option<i32>,
+    }
+
+    // Keywords and names that aren't identifiers.
+    #[allow(dead_code)]
+    #[derive(Debug, Clone, crate::Entity)]
+    #[orm(table = "order")]
+    pub struct Kind {
+        #[orm(primary_key)]
+        pub id: i32,
+        pub r#type: String,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug, Clone, crate::Entity)]
+    #[orm(table = "order-items")]
+    pub struct OrderItem {
+        #[orm(primary_key)]
+        pub id: i32,
+        #[orm(references = "Kind.type")]
+        pub kind_type: String,
+    }
+
     #[test]
     fn test() {
-        println!("{}", Person::find_related::<PersonCircle>().query().sql());
-        println!("{}", PersonCircle::find_related::<Person>().query().sql());
-        println!("{}", Person::find_related::<Circle>().query().sql());
+        println!(
+            "{}",
+            Person::find_related::<PersonCircle>()
+                .query::<Postgres>()
+                .sql()
+        );
+        println!(
+            "{}",
+            PersonCircle::find_related::<Person>()
+                .query::<Postgres>()
+                .sql()
+        );
+        println!(
+            "{}",
+            Person::find_related::<Circle>().query::<Postgres>().sql()
+        );
 
         let query = InsertPerson {
             name: "Nir".to_string(),
             ..Default::default()
         };
-        println!("{}", query.insert_query().into_sql());
+        println!("{}", query.insert_query::<Postgres>().into_sql());
 
         let update = person::Update::new(1)
             .name("Gil".to_string())
             .addr("5000 Forbes".to_string())
             .age(10)
-            .query()
+            .query::<Postgres>()
+            .unwrap()
             .into_sql();
         println!("{}", update);
     }
+
+    #[test]
+    fn update_only_set_columns() {
+        let update = person::Update::new(1).name("Gil").set_null_age();
+        assert_eq!(
+            update.query::<Postgres>().unwrap().into_sql(),
+            "UPDATE \"people\" SET \"name\" = $1, \"age\" = $2 WHERE \"id\" = $3"
+        );
+        assert!(person::Update::new(1).query::<Postgres>().is_none());
+    }
+
+    #[test]
+    fn filter_combinators() {
+        let sql = Person::find()
+            .filter(Person::Name.eq("Nir").or(Person::Age.is_null(true)))
+            .filter(Person::Addr.is_null(false).not())
+            .query::<Postgres>()
+            .into_sql();
+        assert!(sql.ends_with(
+            "WHERE  ( ( \"people\".\"name\" = $1 OR \"people\".\"age\" IS NULL) AND NOT ( \"people\".\"addr\" IS NOT NULL))"
+        ));
+    }
+
+    #[test]
+    fn comparison_operators() {
+        let sql = Person::find()
+            .filter(Person::Age.between(18, 65))
+            .filter(Person::Id.in_list(vec![1, 2, 3]))
+            .filter(Person::Name.starts_with("50%_"))
+            .query::<Postgres>()
+            .into_sql();
+        assert!(sql.ends_with(
+            "WHERE  ( ( \"people\".\"age\" BETWEEN $1 AND $2 AND \"people\".\"id\" = ANY($3)) \
+             AND \"people\".\"name\" LIKE $4)"
+        ));
+    }
+
+    #[test]
+    fn order_limit_offset() {
+        let sql = Person::find()
+            .filter(Person::Name.eq("Nir"))
+            .order_by_nulls(Person::Age, Order::Desc, Nulls::Last)
+            .order_by(Person::Id, Order::Asc)
+            .limit(20)
+            .offset(40)
+            .query::<Postgres>()
+            .into_sql();
+        assert!(sql.ends_with(
+            "ORDER BY \"people\".\"age\" DESC NULLS LAST, \"people\".\"id\" ASC LIMIT $2 OFFSET $3"
+        ));
+    }
+
+    #[test]
+    fn keyset_filter() {
+        let sql = Person::find()
+            .filter(KeysetAfter::new(
+                &(Person::Name, Person::Id),
+                ("Nir".to_string(), 1),
+            ))
+            .query::<Postgres>()
+            .into_sql();
+        assert!(sql.ends_with("WHERE  (\"people\".\"name\", \"people\".\"id\") > ($1, $2)"));
+    }
+
+    #[tokio::test]
+    async fn paginate_page_size() {
+        use crate::error::Error;
+
+        // Rejected before connecting, so the pool is never used.
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        for size in [0, -1, i64::MAX] {
+            let page = Person::find()
+                .paginate_after(Person::Id, None, size, &pool)
+                .await;
+            assert!(matches!(page, Err(Error::InvalidPageSize(s)) if s == size));
+        }
+    }
+
+    #[test]
+    fn table_aliases() -> Result<(), crate::error::Error> {
+        let inviter = Person::alias("inviter");
+        let invitee = Person::alias("invitee");
+        let sql = Invite::find()
+            .join_as(
+                JoinTy::Inner,
+                invite::Relation::Inviter.def().rev(),
+                &inviter,
+            )?
+            .join_as(
+                JoinTy::Inner,
+                invite::Relation::Invitee.def().rev(),
+                &invitee,
+            )?
+            .col((inviter.col(Person::Name), invitee))
+            .filter(inviter.col(Person::Age).gt(18))
+            .query::<Postgres>()
+            .into_sql();
+        assert!(
+            sql.starts_with("SELECT \"inviter\".\"name\" AS \"inviter__name\", \"invitee\".\"id\" AS \"invitee__id\", ")
+        );
+        assert!(sql.contains("INNER JOIN \"people\" AS \"inviter\" ON \"inviter\".\"id\" = \"invites\".\"inviter_id\"\n"));
+        assert!(sql.contains("INNER JOIN \"people\" AS \"invitee\" ON \"invitee\".\"id\" = \"invites\".\"invitee_id\"\n"));
+        assert!(sql.ends_with("WHERE  \"inviter\".\"age\" > $1"));
+
+        let wrong_way =
+            Invite::find().join_as(JoinTy::Inner, invite::Relation::Inviter.def(), &inviter);
+        assert!(matches!(
+            wrong_way,
+            Err(crate::error::Error::RelationMismatch { from, table }) if from == "invites" && table == "people"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn recursive() {
+        let sql = Category::find()
+            .filter(Category::Id.eq(1))
+            .recursive(category::Relation::Parent.def())
+            .max_depth(3)
+            .query()
+            .into_sql();
+        assert_eq!(
+            sql,
+            "WITH RECURSIVE \"categories_tree\" AS (SELECT \"categories\".*, 0 AS __depth, \
+             ARRAY[ROW(\"categories\".\"parent_id\", \"categories\".\"id\")] AS __path FROM \"categories\"\n\
+             WHERE  \"categories\".\"id\" = $1 \
+             UNION ALL SELECT \"categories\".*, \"categories_tree\".__depth + 1, \
+             \"categories_tree\".__path || ROW(\"categories\".\"parent_id\", \"categories\".\"id\") \
+             FROM \"categories\" \
+             INNER JOIN \"categories_tree\" ON \"categories\".\"parent_id\" = \"categories_tree\".\"id\" \
+             WHERE NOT ROW(\"categories\".\"parent_id\", \"categories\".\"id\") = ANY(\"categories_tree\".__path) AND \"categories_tree\".__depth < $2)\n\
+             SELECT \"categories_tree\".\"id\" AS \"categories_tree__id\", \
+             \"categories_tree\".\"name\" AS \"categories_tree__name\", \
+             \"categories_tree\".\"parent_id\" AS \"categories_tree__parent_id\", \
+             \"categories_tree\".__depth AS __depth FROM \"categories_tree\" ORDER BY \"categories_tree\".__depth"
+        );
+    }
+
+    #[test]
+    fn quoted_identifiers() {
+        let col = Col::new(Iden::new("user").unwrap(), "Say \"hi\"".into());
+        assert_eq!(col.to_string(), "\"user\".\"Say \"\"hi\"\"\"");
+        assert_eq!(Person::all_col().to_string(), "\"people\".*");
+        assert!(matches!(
+            Iden::new(""),
+            Err(crate::error::Error::InvalidIdentifier { .. })
+        ));
+        assert!(Iden::new("a\0b").is_err());
+        assert!(Iden::new("a".repeat(64)).is_err());
+        assert!(Iden::new("a".repeat(63)).is_ok());
+    }
+
+    #[test]
+    fn delete() {
+        assert_eq!(
+            Person::delete_by_id(1)
+                .query::<Postgres>()
+                .unwrap()
+                .into_sql(),
+            "DELETE FROM \"people\" WHERE \"people\".\"id\" = $1"
+        );
+        assert!(Person::delete().query::<Postgres>().is_err());
+        assert_eq!(
+            Person::delete()
+                .delete_all()
+                .query::<Postgres>()
+                .unwrap()
+                .into_sql(),
+            "DELETE FROM \"people\""
+        );
+    }
+
+    #[test]
+    fn upsert() {
+        let insert = InsertPerson {
+            name: "Nir".to_string(),
+            ..Default::default()
+        };
+        let upsert = insert
+            .on_conflict(Person::Name)
+            .do_update((Person::Addr, Person::Age));
+        assert!(upsert.query::<Postgres>().unwrap().into_sql().ends_with(
+            "ON CONFLICT (\"name\") DO UPDATE SET \"addr\" = EXCLUDED.\"addr\", \"age\" = EXCLUDED.\"age\""
+        ));
+        let upsert = insert.on_conflict(Person::Name).do_nothing();
+        assert!(upsert
+            .query::<Postgres>()
+            .unwrap()
+            .into_sql()
+            .ends_with("ON CONFLICT (\"name\") DO NOTHING"));
+        let upsert = insert
+            .on_conflict(Person::Name)
+            .do_update_set(person::Update::default());
+        assert!(matches!(
+            upsert.query::<Postgres>(),
+            Err(crate::error::Error::EmptyConflictUpdate)
+        ));
+        let upsert = insert.on_conflict(()).do_nothing();
+        assert!(upsert
+            .query::<Postgres>()
+            .unwrap()
+            .into_sql()
+            .ends_with(") ON CONFLICT DO NOTHING"));
+        let upsert = insert.on_conflict(()).do_update(Person::Age);
+        assert!(matches!(
+            upsert.query::<Postgres>(),
+            Err(crate::error::Error::EmptyConflictTarget)
+        ));
+    }
+
+    #[test]
+    fn insert_defaults() {
+        let insert = InsertCircle {
+            name: "RND".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            insert.insert_query::<Postgres>().into_sql(),
+            "INSERT INTO \"circles\" (\"name\") VALUES ($1)"
+        );
+        let insert = InsertCircle {
+            is_connected: Some(false),
+            ..insert
+        };
+        assert_eq!(
+            insert.insert_query::<Postgres>().into_sql(),
+            "INSERT INTO \"circles\" (\"name\", \"is_connected\") VALUES ($1, $2)"
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn insert_returning() -> Result<(), crate::error::Error> {
+        use sqlx::{Connection, SqliteConnection};
+
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await?;
+        sqlx::query(
+            "CREATE TABLE circles (id INTEGER PRIMARY KEY, name TEXT NOT NULL, \
+             is_connected BOOLEAN NOT NULL DEFAULT TRUE)",
+        )
+        .execute(&mut conn)
+        .await?;
+        let insert = InsertCircle {
+            name: "RND".to_string(),
+            ..Default::default()
+        };
+        let rnd = insert.insert_returning(&mut conn).await?;
+        assert_eq!(
+            (rnd.id, rnd.name.as_str(), rnd.is_connected),
+            (1, "RND", true)
+        );
+        let sales = InsertCircle {
+            name: "Sales".to_string(),
+            is_connected: Some(false),
+        }
+        .insert_returning(&mut conn)
+        .await?;
+        assert_eq!((sales.id, sales.is_connected), (2, false));
+        Ok(())
+    }
+
+    #[test]
+    fn derived_entity() {
+        use crate::common::Decoder;
+        use sqlx::postgres::PgRow;
+
+        let insert = InsertMember {
+            nickname: "nir".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            insert.insert_query::<Postgres>().into_sql(),
+            "INSERT INTO \"members\" (\"nickname\", \"circle_id\") VALUES ($1, $2)"
+        );
+        let update = member::Update::new(1).set_null_circle_id();
+        assert_eq!(
+            update.query::<Postgres>().unwrap().into_sql(),
+            "UPDATE \"members\" SET \"circle_id\" = $1 WHERE \"id\" = $2"
+        );
+        let sql = Circle::find_related_via::<MemberEntity>(member::Relation::HomeCircle)
+            .unwrap()
+            .col(MemberEntity::Nickname)
+            .query::<Postgres>()
+            .into_sql();
+        assert_eq!(
+            sql,
+            "SELECT \"members\".\"nickname\" AS \"members__nickname\" FROM \"members\"\n\
+             LEFT JOIN \"circles\" ON \"circles\".\"id\" = \"members\".\"circle_id\"\n"
+        );
+        // The struct itself is the decoded `Row`.
+        let _: fn(&PgRow) -> Result<Member, sqlx::Error> =
+            |row| Decoder::<Postgres>::from_row(&MemberEntity, row);
+
+        let insert = InsertKind {
+            r#type: "big".to_string(),
+        };
+        assert_eq!(
+            insert.insert_query::<Postgres>().into_sql(),
+            "INSERT INTO \"order\" (\"type\") VALUES ($1)"
+        );
+        let sql = OrderItemEntity::find_related::<KindEntity>()
+            .col(KindEntity::Type)
+            .query::<Postgres>()
+            .into_sql();
+        assert_eq!(
+            sql,
+            "SELECT \"order\".\"type\" AS \"order__type\" FROM \"order\"\n\
+             LEFT JOIN \"order-items\" ON \"order-items\".\"kind_type\" = \"order\".\"type\"\n"
+        );
+    }
+
+    #[test]
+    fn aliased_columns() {
+        let sql = Person::find_related::<Circle>()
+            .col((Circle::Name, Person, Circle))
+            .query::<Postgres>()
+            .into_sql();
+        assert!(sql.starts_with(
+            "SELECT \"circles\".\"name\" AS \"circles__name\", \
+             \"people\".\"id\" AS \"people__id\", \"people\".\"name\" AS \"people__name\", \
+             \"people\".\"addr\" AS \"people__addr\", \"people\".\"age\" AS \"people__age\", \
+             \"circles\".\"id\" AS \"circles__id\", \"circles\".\"name\" AS \"circles__name\", \
+             \"circles\".\"is_connected\" AS \"circles__is_connected\" FROM \"circles\""
+        ));
+    }
+
+    #[test]
+    fn unambiguous_aliases() {
+        let alias = |tbl: &str, col: &str| Col::new(tbl.into(), col.into()).alias();
+        assert_eq!(alias("people", "first_name").as_str(), "people__first_name");
+        assert_ne!(alias("a__b", "c"), alias("a", "b__c"));
+        assert_ne!(alias("a_", "b"), alias("a", "_b"));
+        let long = alias(&"t".repeat(40), &"c".repeat(40));
+        assert!(long.as_str().starts_with("__"));
+        assert!(Iden::new(long.as_str()).is_ok());
+        assert_ne!(long, alias(&"t".repeat(40), &"c".repeat(41)));
+        let people = Person::alias(&"t".repeat(60));
+        let sum = Col::from(sum(people.col(Person::Age)));
+        assert!(sum.alias().as_str().starts_with("__"));
+    }
+
+    #[test]
+    fn aggregates() {
+        let sql = Person::find_related::<Circle>()
+            .col((
+                Circle::Name,
+                count_star(),
+                count_distinct(Person::Name),
+                avg(Person::Age),
+                max(Person::Addr),
+            ))
+            .filter(Person::Age.gte(18))
+            .group_by(Circle::Name)
+            .having(count(Person::Id).gt(1))
+            .order_by(sum(Person::Age), Order::Desc)
+            .query::<Postgres>()
+            .into_sql();
+        assert!(sql.starts_with(
+            "SELECT \"circles\".\"name\" AS \"circles__name\", COUNT(*) AS \"count__star\", \
+             COUNT(DISTINCT \"people\".\"name\") AS \"count_distinct__people__name\", \
+             CAST(AVG(\"people\".\"age\") AS DOUBLE PRECISION) AS \"avg__people__age\", \
+             MAX(\"people\".\"addr\") AS \"max__people__addr\" FROM \"circles\""
+        ));
+        assert!(sql.ends_with(
+            "WHERE  \"people\".\"age\" >= $1 GROUP BY \"circles\".\"name\" \
+             HAVING  COUNT(\"people\".\"id\") > $2 ORDER BY CAST(SUM(\"people\".\"age\") AS BIGINT) DESC"
+        ));
+    }
+
+    #[test]
+    fn named_relations() {
+        let sql = Person::find_related_via::<Invite>(invite::Relation::Invitee)
+            .unwrap()
+            .col(Invite::Id)
+            .filter(Person::Id.eq(1))
+            .query::<Postgres>()
+            .into_sql();
+        assert_eq!(
+            sql,
+            "SELECT \"invites\".\"id\" AS \"invites__id\" FROM \"invites\"\n\
+             LEFT JOIN \"people\" ON \"people\".\"id\" = \"invites\".\"invitee_id\"\n\
+             WHERE  \"people\".\"id\" = $1"
+        );
+    }
+
+    #[test]
+    fn self_relations() {
+        assert!(matches!(
+            Team::find_related_via::<Team>(team::Relation::SuperTeam),
+            Err(crate::error::Error::SelfRelation { table }) if table == "teams"
+        ));
+        let child = Team::alias("child");
+        let sql = Team::find_related_via_as::<Team>(team::Relation::SuperTeam, &child)
+            .unwrap()
+            .col(Team::Name)
+            .filter(child.col(Team::Name).eq("RND"))
+            .query::<Postgres>()
+            .into_sql();
+        assert_eq!(
+            sql,
+            "SELECT \"teams\".\"name\" AS \"teams__name\" FROM \"teams\"\n\
+             LEFT JOIN \"teams\" AS \"child\" ON \"child\".\"super_team_id\" = \"teams\".\"id\"\n\
+             WHERE  \"child\".\"name\" = $1"
+        );
+        let admin = Team::alias("admin");
+        let sql = Team::find_related_via_as::<Team>(team::Relation::AdminTeam.def().rev(), &admin)
+            .unwrap()
+            .col(Team::Name)
+            .filter(admin.col(Team::Id).eq(1))
+            .query::<Postgres>()
+            .into_sql();
+        assert!(sql.contains(
+            "LEFT JOIN \"teams\" AS \"admin\" ON \"admin\".\"id\" = \"teams\".\"admin_team_id\"\n"
+        ));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn nullable_left_join() -> Result<(), crate::error::Error> {
+        use crate::common::Nullable;
+        use sqlx::{Connection, SqliteConnection};
+
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await?;
+        sqlx::query(
+            "CREATE TABLE teams (id INTEGER PRIMARY KEY, name TEXT NOT NULL, \
+             super_team_id INTEGER REFERENCES teams(id), admin_team_id INTEGER REFERENCES teams(id))",
+        )
+        .execute(&mut conn)
+        .await?;
+        let root = InsertTeam {
+            name: "Root".to_string(),
+            ..Default::default()
+        }
+        .insert(&mut conn)
+        .await?;
+        InsertTeam {
+            name: "RND".to_string(),
+            super_team_id: Some(root),
+            ..Default::default()
+        }
+        .insert(&mut conn)
+        .await?;
+
+        let parent = Team::alias("parent");
+        let teams = Team::find()
+            .join_as(JoinTy::Left, team::Relation::SuperTeam.def().rev(), &parent)?
+            .col((
+                Team::Name,
+                Nullable(parent.col(Team::Name)),
+                Nullable(parent.clone()),
+            ))
+            .order_by(Team::Id, Order::Asc)
+            .all(&mut conn)
+            .await?;
+        let (name, parent_name, parent_team) = &teams[0];
+        assert_eq!((name.as_str(), parent_name), ("Root", &None));
+        assert!(parent_team.is_none());
+        let (name, parent_name, parent_team) = &teams[1];
+        assert_eq!(
+            (name.as_str(), parent_name.as_deref()),
+            ("RND", Some("Root"))
+        );
+        assert_eq!(parent_team.as_ref().map(|team| team.id), Some(root));
+        Ok(())
+    }
+
+    /// The Postgres of the demo, see the `justfile`. The tests using it are
+    /// ignored by default, run them with `cargo test -- --ignored`.
+    async fn postgres() -> Result<sqlx::PgPool, sqlx::Error> {
+        let opts: sqlx::postgres::PgConnectOptions = "postgres://localhost:5432".parse()?;
+        sqlx::PgPool::connect_with(opts.username("postgres").password("postgres")).await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres"]
+    async fn transaction_options() -> Result<(), crate::error::Error> {
+        use crate::transaction::{IsolationLevel, TransactionOptions};
+
+        const FAIL: &str = "DO $$ BEGIN RAISE EXCEPTION 'retry' USING ERRCODE = '40001'; END $$";
+        let pool = postgres().await?;
+        let options = TransactionOptions::new()
+            .isolation(IsolationLevel::Serializable)
+            .retries(2);
+        let mut attempts = 0;
+        let level = options
+            .run(&pool, async |tx| {
+                attempts += 1;
+                if attempts < 3 {
+                    sqlx::query(FAIL).execute(&mut **tx).await?;
+                }
+                let level: String =
+                    sqlx::query_scalar("SELECT current_setting('transaction_isolation')")
+                        .fetch_one(&mut **tx)
+                        .await?;
+                Ok(level)
+            })
+            .await?;
+        assert_eq!((level.as_str(), attempts), ("serializable", 3));
+
+        let mut attempts = 0;
+        let res = options
+            .run(&pool, async |tx| {
+                attempts += 1;
+                sqlx::query(FAIL).execute(&mut **tx).await?;
+                Ok(())
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Postgres"]
+    async fn stream_batched_cursors() -> Result<(), crate::error::Error> {
+        use futures::{StreamExt, TryStreamExt};
+        use sqlx::Connection;
+
+        // A temporary `people`, which shadows the demo's on its connection.
+        const PEOPLE: [&str; 2] = [
+            "CREATE TEMP TABLE people (id INT PRIMARY KEY, name TEXT NOT NULL, addr TEXT, age INT)",
+            "INSERT INTO people (id, name) SELECT n, 'p' || n FROM generate_series(1, 10) n",
+        ];
+        let ids = || {
+            Person::find()
+                .col(Person::Id)
+                .order_by(Person::Id, Order::Asc)
+        };
+        let all: Vec<i32> = (1..=10).collect();
+        let pool = postgres().await?;
+        let mut conns = Vec::new();
+        for _ in 0..2 {
+            let mut conn = pool.acquire().await?.detach();
+            for sql in PEOPLE {
+                sqlx::query(sql).execute(&mut conn).await?;
+            }
+            conns.push(conn);
+        }
+        let [lhs, rhs] = conns.as_mut_slice() else {
+            unreachable!()
+        };
+
+        // Each stream holds the transaction while it lives, so streams on one
+        // transaction follow each other, here after one dropped mid-cursor.
+        let mut tx = lhs.begin().await?;
+        let first: Vec<i32> = ids()
+            .stream_batched(&mut *tx, 3)
+            .take(4)
+            .try_collect()
+            .await?;
+        assert_eq!(first, all[..4]);
+        let second: Vec<i32> = ids().stream_batched(&mut *tx, 4).try_collect().await?;
+        let third: Vec<i32> = ids().stream_batched(&mut *tx, 20).try_collect().await?;
+        assert_eq!((second.as_slice(), third.as_slice()), (&all[..], &all[..]));
+        tx.commit().await?;
+
+        // Streams on two connections, each in its own transaction, advance
+        // in lockstep.
+        let pairs: Vec<_> = ids()
+            .stream_batched(lhs, 3)
+            .zip(ids().stream_batched(rhs, 4))
+            .collect()
+            .await;
+        let (lhs, rhs): (Vec<i32>, Vec<i32>) = pairs
+            .into_iter()
+            .map(|(lhs, rhs)| Ok((lhs?, rhs?)))
+            .collect::<Result<Vec<_>, sqlx::Error>>()?
+            .into_iter()
+            .unzip();
+        assert_eq!((lhs, rhs), (all.clone(), all));
+        Ok(())
+    }
+
+    #[cfg(feature = "mysql")]
+    #[test]
+    fn mysql_rendering() {
+        use crate::sql::ToSql;
+        use sqlx::MySql;
+
+        let sql = Person::find_related::<Circle>()
+            .col((Circle::Name, sum(Person::Age)))
+            .filter(Person::Name.ilike("nir"))
+            .group_by(Circle::Name)
+            .limit(10)
+            .query::<MySql>()
+            .into_sql();
+        assert_eq!(
+            sql,
+            "SELECT `circles`.`name` AS `circles__name`, \
+             CAST(SUM(`people`.`age`) AS SIGNED) AS `sum__people__age` FROM `circles`\n\
+             LEFT JOIN `person_circle` ON `person_circle`.`circle_id` = `circles`.`id`\n\
+             LEFT JOIN `people` ON `people`.`id` = `person_circle`.`person_id`\n\
+             WHERE  `people`.`name` LIKE ? GROUP BY `circles`.`name` LIMIT ?"
+        );
+        assert_eq!(
+            InsertCircle::default().insert_query::<MySql>().into_sql(),
+            "INSERT INTO `circles` (`name`) VALUES (?)"
+        );
+        let update = person::Update::new(1).name("Gil");
+        assert_eq!(
+            update.query::<MySql>().unwrap().into_sql(),
+            "UPDATE `people` SET `name` = ? WHERE `id` = ?"
+        );
+        let col = Col::new("a`b".into(), "c".into());
+        assert_eq!(col.sql::<MySql>().to_string(), "`a``b`.`c`");
+
+        let insert = InsertPerson {
+            name: "Nir".to_string(),
+            ..Default::default()
+        };
+        let upsert = insert
+            .on_conflict(Person::Name)
+            .do_update((Person::Addr, Person::Age));
+        assert!(upsert.query::<MySql>().unwrap().into_sql().ends_with(
+            " ON DUPLICATE KEY UPDATE `id` = LAST_INSERT_ID(`id`), \
+             `addr` = VALUES(`addr`), `age` = VALUES(`age`)"
+        ));
+        let sql = insert
+            .on_conflict(Person::Name)
+            .do_nothing()
+            .query::<MySql>()
+            .unwrap()
+            .into_sql();
+        assert!(sql.ends_with(" ON DUPLICATE KEY UPDATE `id` = `id`"));
+        let upsert = insert.on_conflict(()).do_nothing();
+        assert_eq!(upsert.query::<MySql>().unwrap().into_sql(), sql);
+        let upsert = insert.on_conflict(()).do_update(Person::Age);
+        assert!(matches!(
+            upsert.query::<MySql>(),
+            Err(crate::error::Error::EmptyConflictTarget)
+        ));
+        let upsert = insert
+            .on_conflict(Person::Name)
+            .do_update_set(person::Update::default().age(Some(30)));
+        assert!(upsert
+            .query::<MySql>()
+            .unwrap()
+            .into_sql()
+            .ends_with(" ON DUPLICATE KEY UPDATE `id` = LAST_INSERT_ID(`id`), `age` = ?"));
+
+        let sql = Person::find()
+            .filter(Person::Age.is_distinct_from(30))
+            .query::<MySql>()
+            .into_sql();
+        assert!(sql.ends_with("WHERE  NOT (`people`.`age` <=> ?)"));
+        let sql = Person::find()
+            .order_by_nulls(Person::Age, Order::Desc, Nulls::Last)
+            .order_by_nulls(Person::Name, Order::Asc, Nulls::First)
+            .query::<MySql>()
+            .into_sql();
+        assert!(sql.ends_with(
+            " ORDER BY `people`.`age` IS NULL, `people`.`age` DESC, \
+             `people`.`name` IS NULL DESC, `people`.`name` ASC"
+        ));
+        let sql = Person::find().offset(40).query::<MySql>().into_sql();
+        assert!(sql.ends_with(" LIMIT 18446744073709551615 OFFSET ?"));
+        let sql = Person::find()
+            .offset(40)
+            .query::<sqlx::Postgres>()
+            .into_sql();
+        assert!(sql.ends_with(" OFFSET $1") && !sql.contains("LIMIT"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_insert_many() -> Result<(), crate::error::Error> {
+        use sqlx::{Connection, SqliteConnection};
+
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await?;
+        sqlx::query(
+            "CREATE TABLE circles (id INTEGER PRIMARY KEY, name TEXT NOT NULL, \
+             is_connected BOOLEAN NOT NULL DEFAULT TRUE)",
+        )
+        .execute(&mut conn)
+        .await?;
+        // `changes()` counts the rows written by the last statement.
+        let changes = "SELECT changes()";
+
+        let set = InsertCircle::insert_many(
+            ["RND", "Sales", "Ops"].map(|name| InsertCircle {
+                name: name.to_string(),
+                is_connected: Some(false),
+            }),
+            &mut conn,
+        )
+        .await?;
+        assert_eq!(set, [1, 2, 3]);
+        let written: i64 = sqlx::query_scalar(changes).fetch_one(&mut conn).await?;
+        assert_eq!(written, 3);
+
+        let defaulted = InsertCircle::insert_many(
+            ["HR", "Legal"].map(|name| InsertCircle {
+                name: name.to_string(),
+                ..Default::default()
+            }),
+            &mut conn,
+        )
+        .await?;
+        assert_eq!(defaulted, [4, 5]);
+        let written: i64 = sqlx::query_scalar(changes).fetch_one(&mut conn).await?;
+        assert_eq!(written, 1);
+        let connected: Vec<bool> =
+            sqlx::query_scalar("SELECT is_connected FROM circles ORDER BY id")
+                .fetch_all(&mut conn)
+                .await?;
+        assert_eq!(connected, [false, false, false, true, true]);
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_backend() -> Result<(), crate::error::Error> {
+        use sqlx::{Connection, SqliteConnection};
+
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await?;
+        for sql in [
+            "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT NOT NULL, addr TEXT, age INTEGER)",
+            "CREATE TABLE circles (id INTEGER PRIMARY KEY, name TEXT NOT NULL, \
+             is_connected BOOLEAN NOT NULL DEFAULT TRUE)",
+            "CREATE TABLE person_circle (person_id INTEGER NOT NULL, circle_id INTEGER NOT NULL)",
+            "CREATE TABLE teams (id INTEGER PRIMARY KEY, name TEXT NOT NULL, \
+             super_team_id INTEGER REFERENCES teams(id), admin_team_id INTEGER REFERENCES teams(id))",
+        ] {
+            sqlx::query(sql).execute(&mut conn).await?;
+        }
+
+        let nir = InsertPerson {
+            name: "Nir_1".to_string(),
+            age: Some(30),
+            ..Default::default()
+        }
+        .insert(&mut conn)
+        .await?;
+        let circles = InsertCircle::insert_many(
+            ["RND", "Sales"].map(|name| InsertCircle {
+                name: name.to_string(),
+                ..Default::default()
+            }),
+            &mut conn,
+        )
+        .await?;
+        assert_eq!(circles.len(), 2);
+        InsertPersonCircle {
+            person_id: nir,
+            circle_id: circles[0],
+        }
+        .insert(&mut conn)
+        .await?;
+
+        let names = Person::find()
+            .col(Person::Name)
+            .filter(Person::Name.starts_with("nir_"))
+            .filter(Person::Id.in_list([nir, nir + 1]))
+            .all(&mut conn)
+            .await?;
+        assert_eq!(names, ["Nir_1"]);
+        assert!(
+            !Person::find()
+                .filter(Person::Name.starts_with("Nir%"))
+                .exists(&mut conn)
+                .await?
+        );
+        assert_eq!(Circle::find().count(&mut conn).await?, 2);
+
+        let loaded = Person::find().load_related::<Circle, _>(&mut conn).await?;
+        assert_eq!(loaded[0].1[0].name, "RND");
+        assert!(loaded[0].1[0].is_connected);
+
+        let root = InsertTeam {
+            name: "Root".to_string(),
+            ..Default::default()
+        }
+        .insert(&mut conn)
+        .await?;
+        InsertTeam {
+            name: "RND".to_string(),
+            super_team_id: Some(root),
+            admin_team_id: Some(root),
+        }
+        .insert(&mut conn)
+        .await?;
+        let admin = Team::alias("admin");
+        let administered =
+            Team::find_related_via_as::<Team>(team::Relation::AdminTeam.def().rev(), &admin)?
+                .col(Team::Name)
+                .filter(admin.col(Team::Name).eq("Root"))
+                .all(&mut conn)
+                .await?;
+        assert_eq!(administered, ["RND"]);
+        let child = Team::alias("child");
+        let supers = Team::find_related_via_as::<Team>(team::Relation::SuperTeam, &child)?
+            .col(Team::Name)
+            .filter(child.col(Team::Name).eq("RND"))
+            .all(&mut conn)
+            .await?;
+        assert_eq!(supers, ["Root"]);
+        let skipped = Team::find()
+            .col(Team::Name)
+            .order_by(Team::Id, Order::Asc)
+            .offset(1)
+            .all(&mut conn)
+            .await?;
+        assert_eq!(skipped, ["RND"]);
+
+        let person = person::Update::new(nir)
+            .set_null_age()
+            .returning(&mut conn)
+            .await?;
+        assert_eq!(person.age, None);
+        let deleted = Person::delete_by_id(nir).returning(&mut conn).await?;
+        assert_eq!(deleted[0].name, "Nir_1");
+        Ok(())
+    }
 }
@@ -1,12 +1,13 @@
-use std::i64;
-
 use easy_orm::{
-    common::EntityTrait,
+    aggregate::{count, min},
+    common::{EntityTrait, Nullable},
     data_table, many_to_many,
     relations::RelationTrait,
-    sql::IntoCol,
+    sql::{Filter, IntoCol, JoinTy, Order},
+    transaction::{transaction, IsolationLevel, TransactionOptions},
 };
-use sqlx::{migrate::MigrateError, postgres::PgConnectOptions, PgPool};
+use futures::TryStreamExt;
+use sqlx::{migrate::MigrateError, postgres::PgConnectOptions, PgPool, Postgres};
 
 struct Db {
     pool: PgPool,
@@ -51,9 +52,9 @@ data_table!(CakeFilling of cake_fillings {
 data_table!(Circle of circles {
     [id: i64],
     name: String,
-    is_connected: bool,
-    super_circle_id: Option<i64>,
-    admin_circle_id: Option<i64>,
+    is_connected: bool = default,
+    super_circle_id: Option<i64> => Circle.id as SuperCircle,
+    admin_circle_id: Option<i64> => Circle.id as AdminCircle,
 });
 
 data_table!(Person of people {
@@ -116,25 +117,30 @@ async fn main() {
     let (rnd_id, rnd_name, rnd) = Circle::find()
         .col((Circle::Id, Circle::Name, Circle))
         .filter(Circle::Name.eq("RND"))
+        .filter(
+            Circle::IsConnected
+                .eq(true)
+                .or(Circle::SuperCircleId.is_null(true)),
+        )
         .one(&db.pool)
         .await
         .unwrap();
 
     let exist_marketing = Circle::find()
-        .col(())
         .filter(Circle::Name.eq("Marketing"))
-        .query()
-        .build()
-        .fetch_optional(&db.pool)
+        .exists(&db.pool)
+        .await
+        .unwrap();
+
+    let people_count = Person::find()
+        .filter(Person::LastName.is_null(false))
+        .count(&db.pool)
         .await
         .unwrap();
 
     let exist_rnd = Circle::find()
-        .col(())
         .filter(Circle::Name.eq("RND"))
-        .query()
-        .build()
-        .fetch_optional(&db.pool)
+        .exists(&db.pool)
         .await
         .unwrap();
 
@@ -150,12 +156,108 @@ async fn main() {
         .await
         .unwrap();
 
+    let people_with_circles = Circle::find_related::<Person>()
+        .col((Person, Nullable(Circle)))
+        .all(&db.pool)
+        .await
+        .unwrap();
+
+    let parent = Circle::alias("parent");
+    let circles_with_parents = Circle::find()
+        .join_as(
+            JoinTy::Left,
+            circle::Relation::SuperCircle.def().rev(),
+            &parent,
+        )
+        .unwrap()
+        .col((Circle::Name, Nullable(parent.col(Circle::Name))))
+        .all(&db.pool)
+        .await
+        .unwrap();
+
+    let child = Circle::alias("child");
+    let rnd_super_circles =
+        Circle::find_related_via_as::<Circle>(circle::Relation::SuperCircle, &child)
+            .unwrap()
+            .col(Circle::Name)
+            .filter(child.col(Circle::Id).eq(rnd_id))
+            .all(&db.pool)
+            .await
+            .unwrap();
+    let admin = Circle::alias("admin");
+    let administered_by_rnd =
+        Circle::find_related_via_as::<Circle>(circle::Relation::AdminCircle.def().rev(), &admin)
+            .unwrap()
+            .col(Circle::Name)
+            .filter(admin.col(Circle::Id).eq(rnd_id))
+            .all(&db.pool)
+            .await
+            .unwrap();
+
+    let rnd_subtree = Circle::find()
+        .filter(Circle::Id.eq(rnd_id))
+        .recursive(circle::Relation::SuperCircle.def())
+        .all_with_depth(&db.pool)
+        .await
+        .unwrap();
+    let rnd_ancestors = Circle::find()
+        .filter(Circle::Id.eq(rnd_id))
+        .recursive(circle::Relation::SuperCircle.def().rev())
+        .all(&db.pool)
+        .await
+        .unwrap();
+
+    let circle_sizes = Person::find_related::<Circle>()
+        .col((Circle::Name, count(Person::Id), min(Person::FirstName)))
+        .group_by(Circle::Name)
+        .having(count(Person::Id).gt(0))
+        .order_by(count(Person::Id), Order::Desc)
+        .all(&db.pool)
+        .await
+        .unwrap();
+
+    let streamed_names: Vec<String> = Person::find()
+        .col(Person::FirstName)
+        .order_by(Person::Id, Order::Asc)
+        .stream(&db.pool)
+        .try_collect()
+        .await
+        .unwrap();
+    let batched_names: Vec<String> = Person::find()
+        .col(Person::FirstName)
+        .filter(Person::Id.gt(0))
+        .order_by(Person::Id, Order::Asc)
+        .stream_batched(&db.pool, 2)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(streamed_names, batched_names);
+
+    let cakes_with_fillings = Cake::find()
+        .load_related::<Filling, _>(&db.pool)
+        .await
+        .unwrap();
+
+    let cakes_with_fruits = Cake::find()
+        .load_related::<Fruits, _>(&db.pool)
+        .await
+        .unwrap();
+
     let uncircled_people_query = Circle::find_related::<Person>()
         .filter(Circle::Id.is_null(true))
-        .query()
+        .query::<Postgres>()
         .into_sql();
     println!("{}", uncircled_people_query);
 
+    let people_page = Person::find()
+        .paginate_after(Person::Id, None, 2, &db.pool)
+        .await
+        .unwrap();
+    let next_people_page = Person::find()
+        .paginate_after(Person::Id, people_page.next, 2, &db.pool)
+        .await
+        .unwrap();
+
     let itai = InsertPerson {
         first_name: "Itai".to_string(),
         ..Default::default()
@@ -164,14 +266,125 @@ async fn main() {
     .await
     .unwrap();
 
+    let gil = UpdatePerson::new(2)
+        .last_name("Gold".to_string())
+        .returning(&db.pool)
+        .await
+        .unwrap();
+
+    let deleted_itai = Person::delete_by_id(itai)
+        .returning(&db.pool)
+        .await
+        .unwrap();
+
+    let new_fillings = InsertFilling::insert_many(
+        ["Vanilla", "Strawberry"].map(|name| InsertFilling {
+            name: name.to_string(),
+        }),
+        &db.pool,
+    )
+    .await
+    .unwrap();
+
+    let synced_person = InsertPerson {
+        first_name: "Pavan".to_string(),
+        external_identity_provider: Some("ldap".to_string()),
+        external_identity_number: Some("1234".to_string()),
+        ..Default::default()
+    };
+    let synced_id = synced_person
+        .on_conflict((
+            Person::ExternalIdentityProvider,
+            Person::ExternalIdentityNumber,
+        ))
+        .do_update(Person::FirstName)
+        .insert(&db.pool)
+        .await
+        .unwrap();
+
+    let ops = InsertCircle {
+        name: "Ops".to_string(),
+        super_circle_id: Some(4),
+        ..Default::default()
+    }
+    .insert_returning(&db.pool)
+    .await
+    .unwrap();
+
+    let tx_options = TransactionOptions::new()
+        .isolation(IsolationLevel::Serializable)
+        .retries(3);
+    let (tmp_circle_name, nested_failed) = tx_options
+        .run(&db.pool, async |tx| {
+            let id = InsertCircle {
+                name: "Tmp".to_string(),
+                ..Default::default()
+            }
+            .insert(&mut **tx)
+            .await?;
+            // Rolled back to its savepoint, leaving the insert above intact.
+            let nested = transaction(&mut **tx, async |tx| {
+                UpdateCircle::new(id)
+                    .name("Renamed".to_string())
+                    .exec(&mut **tx)
+                    .await?;
+                Err::<(), _>(sqlx::Error::RowNotFound.into())
+            })
+            .await;
+            let name = Circle::find()
+                .col(Circle::Name)
+                .filter(Circle::Id.eq(id))
+                .one(&mut **tx)
+                .await?;
+            Circle::delete_by_id(id).exec(&mut **tx).await?;
+            Ok((name, nested.is_err()))
+        })
+        .await
+        .unwrap();
+
+    let cheese_cake_filling = InsertCakeFilling {
+        cake_id: 2,
+        filling_id: 1,
+    }
+    .insert_returning(&db.pool)
+    .await
+    .unwrap();
+    CakeFilling::delete()
+        .filter(CakeFilling::CakeId.eq(2))
+        .filter(CakeFilling::FillingId.eq(1))
+        .exec(&db.pool)
+        .await
+        .unwrap();
+
     dbg!(
         rnd_id,
         rnd_name,
         rnd,
         rnd_people,
         uncircled_people,
+        people_with_circles,
+        circles_with_parents,
+        rnd_super_circles,
+        administered_by_rnd,
+        rnd_subtree,
+        rnd_ancestors,
+        circle_sizes,
+        streamed_names,
+        cakes_with_fillings,
+        cakes_with_fruits,
         itai,
+        deleted_itai,
+        gil,
+        new_fillings,
+        synced_id,
+        ops,
+        tmp_circle_name,
+        nested_failed,
+        cheese_cake_filling,
+        people_page,
+        next_people_page,
         exist_marketing,
-        exist_rnd
+        exist_rnd,
+        people_count
     );
 }
@@ -1,4 +1,4 @@
-use crate::sql::Col;
+use crate::{common::Selector, sql::Col};
 
 #[derive(Debug, Default)]
 pub struct RelationBuilder {
@@ -36,6 +36,7 @@ impl From<RelationBuilder> for RelationDef {
 ///
 /// Then the relation cake.id --> cake_filling.cake_id belongs to `cake`
 /// since the other column is a referernce to the column in `cake`
+#[derive(Debug, Clone)]
 pub struct RelationDef {
     pub from_col: Col,
     pub to_col: Col,
@@ -51,6 +52,10 @@ impl RelationDef {
 }
 
 pub trait Related<E> {
+    /// The column of `Self` the relation starts from, used to match loaded
+    /// `E`s back to the rows they belong to.
+    type Key: Selector;
+
     fn to() -> RelationDef;
     fn via() -> Option<RelationDef> {
         None
@@ -60,3 +65,10 @@ pub trait Related<E> {
 pub trait RelationTrait {
     fn def(&self) -> RelationDef;
 }
+
+/// Lets a relation be passed in a given direction, e.g. `def().rev()`.
+impl RelationTrait for RelationDef {
+    fn def(&self) -> RelationDef {
+        self.clone()
+    }
+}
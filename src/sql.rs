@@ -1,12 +1,25 @@
-use std::{fmt::Display, marker::PhantomData, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    hash::{DefaultHasher, Hash, Hasher},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use futures::{StreamExt, TryStreamExt};
+use async_stream::try_stream;
+use futures::{Stream, StreamExt, TryStreamExt};
 use itertools::Itertools;
-use sqlx::{Encode, FromRow, PgExecutor, Postgres, QueryBuilder, Type};
+use sqlx::{Acquire, Encode, Executor, PgExecutor, Postgres, QueryBuilder, Type};
 
 use crate::{
-    common::{ColumnList, Selector},
-    relations::RelationDef,
+    aggregate::{AggFn, Aggregate},
+    backend::{self, Backend, InList, SqlType, SupportsReturning},
+    common::{DecodeEntity, Decoder, EntityTrait, Keyset, Selector, TableAlias},
+    error::Error,
+    relations::{Related, RelationDef},
 };
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
@@ -14,9 +27,75 @@ pub struct Iden {
     iden: Arc<String>,
 }
 
-impl Display for Iden {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.iden.fmt(f)
+/// Postgres truncates longer identifiers, so they could silently refer to
+/// something else.
+const MAX_IDEN_LEN: usize = 63;
+
+/// SQL fragments that are written differently depending on the backend,
+/// e.g. identifiers are quoted with backticks on MySQL. `Display` writes
+/// them for Postgres.
+pub trait ToSql {
+    fn fmt_sql<DB: Backend>(&self, f: &mut Formatter<'_>) -> std::fmt::Result;
+
+    /// Displays the fragment as written for `DB`.
+    fn sql<DB: Backend>(&self) -> Sql<'_, Self, DB> {
+        Sql {
+            inner: self,
+            _pha: PhantomData,
+        }
+    }
+}
+
+/// See `ToSql::sql`.
+pub struct Sql<'a, T: ?Sized, DB> {
+    inner: &'a T,
+    _pha: PhantomData<DB>,
+}
+
+impl<T: ToSql + ?Sized, DB: Backend> Display for Sql<'_, T, DB> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.inner.fmt_sql::<DB>(f)
+    }
+}
+
+/// Renders as a quoted identifier, so reserved words like `user` and mixed
+/// case names are taken literally.
+impl ToSql for Iden {
+    fn fmt_sql<DB: Backend>(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let quote = DB::QUOTE;
+        let escaped = self.iden.replace(quote, &format!("{quote}{quote}"));
+        write!(f, "{quote}{escaped}{quote}")
+    }
+}
+
+impl Iden {
+    /// Checks that `iden` can be used as a Postgres identifier, for names
+    /// that are only known at runtime. Fails with `Error::InvalidIdentifier`.
+    pub fn new(iden: impl Into<String>) -> Result<Self, Error> {
+        let iden = iden.into();
+        let problem = if iden.is_empty() {
+            "is empty"
+        } else if iden.contains('\0') {
+            "contains a NUL character"
+        } else if iden.len() > MAX_IDEN_LEN {
+            "is longer than 63 bytes"
+        } else {
+            return Ok(iden.into());
+        };
+        Err(Error::InvalidIdentifier { iden, problem })
+    }
+
+    /// The identifier as it is, unquoted.
+    pub fn as_str(&self) -> &str {
+        &self.iden
+    }
+}
+
+impl From<String> for Iden {
+    fn from(value: String) -> Self {
+        Self {
+            iden: Arc::new(value),
+        }
     }
 }
 
@@ -32,17 +111,75 @@ impl From<&str> for Iden {
 pub struct Col {
     pub tbl: Iden,
     pub col: Iden,
+    agg: Option<Aggregate>,
 }
 
 impl Col {
     pub fn new(tbl: Iden, col: Iden) -> Self {
-        Self { tbl, col }
+        Self {
+            tbl,
+            col,
+            agg: None,
+        }
+    }
+
+    pub(crate) fn aggregate(self, agg: Aggregate) -> Self {
+        Self {
+            agg: Some(agg),
+            ..self
+        }
+    }
+
+    /// The name the column is selected as, unique across the tables of a
+    /// query, e.g. `circles__id`, or `count__circles__id` for `COUNT`.
+    ///
+    /// Names that could make two aliases collide, i.e. that start or end
+    /// with `_` or contain `__`, and aliases that Postgres would truncate
+    /// are replaced by a hash, e.g. `__5f3c0a9e1b2d4c67`. The hash is only
+    /// stable within one build, which is all decoding by alias needs.
+    pub fn alias(&self) -> Iden {
+        let mut parts = Vec::with_capacity(3);
+        if let Some(agg) = self.agg {
+            parts.push(agg.func.name());
+        }
+        match self.col.as_str() {
+            "*" => parts.push("star"),
+            col => parts.extend([self.tbl.as_str(), col]),
+        }
+        let alias = parts.join("__");
+        let plain = |part: &&str| {
+            !part.is_empty()
+                && !part.starts_with('_')
+                && !part.ends_with('_')
+                && !part.contains("__")
+        };
+        if alias.len() <= MAX_IDEN_LEN && parts.iter().all(plain) {
+            return alias.into();
+        }
+        let mut hasher = DefaultHasher::new();
+        parts.hash(&mut hasher);
+        format!("__{:016x}", hasher.finish()).into()
     }
 }
 
-impl Display for Col {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}.{}", self.tbl, self.col)
+impl ToSql for Col {
+    fn fmt_sql<DB: Backend>(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let col = match (self.tbl.as_str(), self.col.as_str()) {
+            ("", "*") => "*".to_string(),
+            (_, "*") => format!("{}.*", self.tbl.sql::<DB>()),
+            _ => format!("{}.{}", self.tbl.sql::<DB>(), self.col.sql::<DB>()),
+        };
+        let Some(agg) = self.agg else {
+            return f.write_str(&col);
+        };
+        let call = match agg.func {
+            AggFn::CountDistinct => format!("COUNT(DISTINCT {})", col),
+            func => format!("{}({})", func.name().to_uppercase(), col),
+        };
+        match agg.cast {
+            Some(cast) => write!(f, "CAST({} AS {})", call, cast.name::<DB>()),
+            None => f.write_str(&call),
+        }
     }
 }
 
@@ -61,28 +198,114 @@ impl Display for JoinTy {
     }
 }
 
+#[derive(Default, Debug, Clone, Copy)]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl Display for Order {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_uppercase())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+impl Display for Nulls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NULLS {}", format!("{:?}", self).to_uppercase())
+    }
+}
+
+pub(crate) struct OrderBy {
+    col: Col,
+    order: Order,
+    nulls: Option<Nulls>,
+}
+
+impl ToSql for OrderBy {
+    fn fmt_sql<DB: Backend>(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let col = self.col.sql::<DB>();
+        match self.nulls {
+            Some(nulls) if DB::NULLS_ORDER => write!(f, "{} {} {}", col, self.order, nulls),
+            Some(Nulls::First) => write!(f, "{} IS NULL DESC, {} {}", col, col, self.order),
+            Some(Nulls::Last) => write!(f, "{} IS NULL, {} {}", col, col, self.order),
+            None => write!(f, "{} {}", col, self.order),
+        }
+    }
+}
+
 pub(crate) struct Join {
     ty: JoinTy,
     tbl: Iden,
+    alias: Option<Iden>,
     from_col: Col,
     to_col: Col,
 }
 
-impl Display for Join {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl ToSql for Join {
+    fn fmt_sql<DB: Backend>(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} JOIN {}", self.ty, self.tbl.sql::<DB>())?;
+        if let Some(alias) = &self.alias {
+            write!(f, " AS {}", alias.sql::<DB>())?;
+        }
         write!(
             f,
-            "{} JOIN {} ON {} = {}",
-            self.ty, self.tbl, self.from_col, self.to_col
+            " ON {} = {}",
+            self.from_col.sql::<DB>(),
+            self.to_col.sql::<DB>()
         )
     }
 }
 
-pub trait Filter<'q>: Sized {
-    fn filter(self, builder: &mut QueryBuilder<'q, Postgres>);
+// The Postgres rendering, which is also what the tests compare against.
+macro_rules! impl_display_via_sql {
+    ($($t:ty),*) => {
+        $(impl Display for $t {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                self.fmt_sql::<Postgres>(f)
+            }
+        })*
+    };
+}
+
+impl_display_via_sql!(Iden, Col, OrderBy, Join);
+
+/// A condition, combinable with `and`, `or` and `not`. How it is rendered
+/// on a given backend is up to `FilterSql`.
+pub trait Filter: Sized {
     fn effective(&self) -> bool {
         true
     }
+
+    fn and<F: Filter>(self, other: F) -> And<Self, F> {
+        And {
+            lhs: self,
+            rhs: other,
+        }
+    }
+
+    fn or<F: Filter>(self, other: F) -> Or<Self, F> {
+        Or {
+            lhs: self,
+            rhs: other,
+        }
+    }
+
+    fn not(self) -> Not<Self> {
+        Not { inner: self }
+    }
+}
+
+/// A filter that can be pushed into a query on `DB`.
+pub trait FilterSql<'q, DB: Backend>: Filter {
+    fn filter(self, builder: &mut QueryBuilder<'q, DB>);
 }
 
 pub trait IntoCol: Into<Col> {
@@ -93,6 +316,82 @@ pub trait IntoCol: Into<Col> {
         }
     }
 
+    fn ne<T>(self, val: T) -> ColCmp<T> {
+        ColCmp::new(self.into(), CmpOp::Ne, val)
+    }
+
+    fn lt<T>(self, val: T) -> ColCmp<T> {
+        ColCmp::new(self.into(), CmpOp::Lt, val)
+    }
+
+    fn lte<T>(self, val: T) -> ColCmp<T> {
+        ColCmp::new(self.into(), CmpOp::Lte, val)
+    }
+
+    fn gt<T>(self, val: T) -> ColCmp<T> {
+        ColCmp::new(self.into(), CmpOp::Gt, val)
+    }
+
+    fn gte<T>(self, val: T) -> ColCmp<T> {
+        ColCmp::new(self.into(), CmpOp::Gte, val)
+    }
+
+    fn like<T>(self, pattern: T) -> ColCmp<T> {
+        ColCmp::new(self.into(), CmpOp::Like, pattern)
+    }
+
+    /// Rendered as `Backend::ILIKE`, e.g. plain `LIKE` on SQLite.
+    fn ilike<T>(self, pattern: T) -> ColCmp<T> {
+        ColCmp::new(self.into(), CmpOp::ILike, pattern)
+    }
+
+    /// Matches values beginning with `prefix`, which is escaped so `%` and
+    /// `_` are taken literally.
+    fn starts_with(self, prefix: &str) -> ColCmp<String> {
+        let pattern = prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        ColCmp::new(self.into(), CmpOp::StartsWith, pattern + "%")
+    }
+
+    /// `<>` that takes `NULL` as a value, rendered as `NOT (col <=> $1)` on
+    /// MySQL.
+    #[allow(clippy::wrong_self_convention)]
+    fn is_distinct_from<T>(self, val: T) -> ColCmp<T> {
+        ColCmp::new(self.into(), CmpOp::IsDistinctFrom, val)
+    }
+
+    fn between<T>(self, low: T, high: T) -> ColBetween<T> {
+        ColBetween {
+            col: self.into(),
+            low,
+            high,
+        }
+    }
+
+    /// Matches the column against a collection of values, rendering
+    /// `col = ANY($1)` on Postgres and `col IN ($1, $2, ..)` elsewhere, see
+    /// `InList`. An empty collection matches nothing.
+    fn in_list<T>(self, vals: T) -> ColIn<T> {
+        ColIn {
+            col: self.into(),
+            negated: false,
+            vals,
+        }
+    }
+
+    /// The negation of `in_list`, rendering `col <> ALL($1)` on Postgres. An
+    /// empty collection matches everything.
+    fn not_in<T>(self, vals: T) -> ColIn<T> {
+        ColIn {
+            col: self.into(),
+            negated: true,
+            vals,
+        }
+    }
+
+    #[allow(clippy::wrong_self_convention)]
     fn is_null(self, val: bool) -> ColNull {
         ColNull {
             col: self.into(),
@@ -107,58 +406,300 @@ pub struct ColEq<T> {
     val: T,
 }
 
-impl<'arg, T> Filter<'arg> for ColEq<T>
+impl<T> Filter for ColEq<T> {}
+
+impl<'arg, DB, T> FilterSql<'arg, DB> for ColEq<T>
 where
-    T: 'arg + Encode<'arg, Postgres> + Type<Postgres> + Send,
+    DB: Backend,
+    T: 'arg + Encode<'arg, DB> + Type<DB>,
 {
-    fn filter(self, builder: &mut QueryBuilder<'arg, Postgres>) {
-        builder.push(format!(" {} = ", self.col));
+    fn filter(self, builder: &mut QueryBuilder<'arg, DB>) {
+        builder.push(format!(" {} = ", self.col.sql::<DB>()));
         builder.push_bind(self.val);
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+enum CmpOp {
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    Like,
+    ILike,
+    /// `LIKE` with an escaped pattern.
+    StartsWith,
+    IsDistinctFrom,
+}
+
+pub struct ColCmp<T> {
+    col: Col,
+    op: CmpOp,
+    val: T,
+}
+
+impl<T> ColCmp<T> {
+    fn new(col: Col, op: CmpOp, val: T) -> Self {
+        Self { col, op, val }
+    }
+}
+
+impl<T> Filter for ColCmp<T> {}
+
+impl<'arg, DB, T> FilterSql<'arg, DB> for ColCmp<T>
+where
+    DB: Backend,
+    T: 'arg + Encode<'arg, DB> + Type<DB>,
+{
+    fn filter(self, builder: &mut QueryBuilder<'arg, DB>) {
+        let col = self.col.sql::<DB>();
+        // The SQL before and after the bound value.
+        let (lhs, rhs) = match (self.op, DB::NULL_SAFE_EQ) {
+            (CmpOp::Ne, _) => (format!("{} <>", col), ""),
+            (CmpOp::Lt, _) => (format!("{} <", col), ""),
+            (CmpOp::Lte, _) => (format!("{} <=", col), ""),
+            (CmpOp::Gt, _) => (format!("{} >", col), ""),
+            (CmpOp::Gte, _) => (format!("{} >=", col), ""),
+            (CmpOp::Like, _) => (format!("{} LIKE", col), ""),
+            (CmpOp::ILike, _) => (format!("{} {}", col, DB::ILIKE), ""),
+            (CmpOp::StartsWith, _) => (format!("{} LIKE", col), DB::LIKE_ESCAPE),
+            (CmpOp::IsDistinctFrom, Some(eq)) => (format!("NOT ({} {}", col, eq), ")"),
+            (CmpOp::IsDistinctFrom, None) => (format!("{} IS DISTINCT FROM", col), ""),
+        };
+        builder.push(format!(" {} ", lhs));
+        builder.push_bind(self.val);
+        builder.push(rhs);
+    }
+}
+
+pub struct ColBetween<T> {
+    col: Col,
+    low: T,
+    high: T,
+}
+
+impl<T> Filter for ColBetween<T> {}
+
+impl<'arg, DB, T> FilterSql<'arg, DB> for ColBetween<T>
+where
+    DB: Backend,
+    T: 'arg + Encode<'arg, DB> + Type<DB>,
+{
+    fn filter(self, builder: &mut QueryBuilder<'arg, DB>) {
+        builder.push(format!(" {} BETWEEN ", self.col.sql::<DB>()));
+        builder.push_bind(self.low);
+        builder.push(" AND ");
+        builder.push_bind(self.high);
+    }
+}
+
+pub struct ColIn<T> {
+    col: Col,
+    negated: bool,
+    vals: T,
+}
+
+impl<T> Filter for ColIn<T> {}
+
+impl<'arg, DB, T> FilterSql<'arg, DB> for ColIn<T>
+where
+    DB: Backend,
+    T: InList<'arg, DB>,
+{
+    fn filter(self, builder: &mut QueryBuilder<'arg, DB>) {
+        self.vals.push_in_list(builder, &self.col, self.negated);
+    }
+}
+
+/// Row comparison `(a, b) > ($1, $2)` selecting everything after a keyset
+/// cursor.
+pub struct KeysetAfter<K: Selector> {
+    cols: Vec<Col>,
+    data: K::Data,
+}
+
+impl<K: Selector> KeysetAfter<K> {
+    pub fn new(cursor: &K, data: K::Data) -> Self {
+        Self {
+            cols: cursor.cols().collect(),
+            data,
+        }
+    }
+}
+
+impl<K: Selector> Filter for KeysetAfter<K> {}
+
+impl<'q, DB, K> FilterSql<'q, DB> for KeysetAfter<K>
+where
+    DB: Backend,
+    K: Keyset<'q, DB>,
+{
+    fn filter(self, builder: &mut QueryBuilder<'q, DB>) {
+        let cols = self.cols.iter().map(|col| col.sql::<DB>()).join(", ");
+        builder.push(format!(" ({}) > (", cols));
+        K::push_values(self.data, &mut builder.separated(", "));
+        builder.push(")");
+    }
+}
+
 pub struct ColNull {
     col: Col,
     is_null: bool,
 }
 
-impl<'arg> Filter<'arg> for ColNull {
-    fn filter(self, builder: &mut QueryBuilder<'arg, Postgres>) {
+impl Filter for ColNull {}
+
+impl<'arg, DB: Backend> FilterSql<'arg, DB> for ColNull {
+    fn filter(self, builder: &mut QueryBuilder<'arg, DB>) {
         let null = if self.is_null { "NULL" } else { "NOT NULL" };
-        builder.push(format!(" {} IS {}", self.col, null));
+        builder.push(format!(" {} IS {}", self.col.sql::<DB>(), null));
     }
 }
 
-impl Filter<'_> for () {
-    fn filter(self, _: &mut QueryBuilder<'_, Postgres>) {}
+impl Filter for () {
     fn effective(&self) -> bool {
         false
     }
 }
 
+impl<DB: Backend> FilterSql<'_, DB> for () {
+    fn filter(self, _: &mut QueryBuilder<'_, DB>) {}
+}
+
+impl<F: Filter> Filter for Option<F> {
+    fn effective(&self) -> bool {
+        self.as_ref().is_some_and(|f| f.effective())
+    }
+}
+
+impl<'q, DB: Backend, F: FilterSql<'q, DB>> FilterSql<'q, DB> for Option<F> {
+    fn filter(self, builder: &mut QueryBuilder<'q, DB>) {
+        if let Some(f) = self {
+            f.filter(builder)
+        }
+    }
+}
+
+/// Joins two filters with `op`, wrapping them in parentheses so the result
+/// can be nested in other combinators. A side that is not effective is
+/// dropped, so `().and(f)` renders just as `f`.
+fn combine<'q, DB, L, R>(op: &str, lhs: L, rhs: R, builder: &mut QueryBuilder<'q, DB>)
+where
+    DB: Backend,
+    L: FilterSql<'q, DB>,
+    R: FilterSql<'q, DB>,
+{
+    match (lhs.effective(), rhs.effective()) {
+        (true, true) => {
+            builder.push(" (");
+            lhs.filter(builder);
+            builder.push(format!(" {}", op));
+            rhs.filter(builder);
+            builder.push(")");
+        }
+        (true, false) => lhs.filter(builder),
+        (false, _) => rhs.filter(builder),
+    }
+}
+
+pub struct And<L, R> {
+    lhs: L,
+    rhs: R,
+}
+
+impl<L: Filter, R: Filter> Filter for And<L, R> {
+    fn effective(&self) -> bool {
+        self.lhs.effective() || self.rhs.effective()
+    }
+}
+
+impl<'q, DB, L, R> FilterSql<'q, DB> for And<L, R>
+where
+    DB: Backend,
+    L: FilterSql<'q, DB>,
+    R: FilterSql<'q, DB>,
+{
+    fn filter(self, builder: &mut QueryBuilder<'q, DB>) {
+        combine("AND", self.lhs, self.rhs, builder)
+    }
+}
+
+pub struct Or<L, R> {
+    lhs: L,
+    rhs: R,
+}
+
+impl<L: Filter, R: Filter> Filter for Or<L, R> {
+    fn effective(&self) -> bool {
+        self.lhs.effective() || self.rhs.effective()
+    }
+}
+
+impl<'q, DB, L, R> FilterSql<'q, DB> for Or<L, R>
+where
+    DB: Backend,
+    L: FilterSql<'q, DB>,
+    R: FilterSql<'q, DB>,
+{
+    fn filter(self, builder: &mut QueryBuilder<'q, DB>) {
+        combine("OR", self.lhs, self.rhs, builder)
+    }
+}
+
+pub struct Not<F> {
+    inner: F,
+}
+
+impl<F: Filter> Filter for Not<F> {
+    fn effective(&self) -> bool {
+        self.inner.effective()
+    }
+}
+
+impl<'q, DB: Backend, F: FilterSql<'q, DB>> FilterSql<'q, DB> for Not<F> {
+    fn filter(self, builder: &mut QueryBuilder<'q, DB>) {
+        builder.push(" NOT (");
+        self.inner.filter(builder);
+        builder.push(")");
+    }
+}
 #[derive(Default)]
-pub struct Select<C, F = ()> {
+pub struct Select<C, F = (), H = ()> {
     from: Iden,
     joins: Vec<Join>,
     filter: F,
+    groups: Vec<Col>,
+    having: H,
+    orders: Vec<OrderBy>,
+    limit: Option<i64>,
+    offset: Option<i64>,
 
-    _pha: PhantomData<C>,
+    sel: C,
 }
 
 impl<C> Select<C> {
-    pub(crate) fn new(name: Iden) -> Self {
+    pub(crate) fn new(name: Iden, sel: C) -> Self {
         Self {
             from: name,
             joins: Default::default(),
             filter: (),
-            _pha: PhantomData,
+            groups: Default::default(),
+            having: (),
+            orders: Default::default(),
+            limit: None,
+            offset: None,
+            sel,
         }
     }
+}
 
+impl<C, F, H> Select<C, F, H> {
     pub fn join(mut self, ty: JoinTy, rel: RelationDef) -> Self {
         let join = Join {
             ty,
             tbl: rel.from_col.tbl.clone(),
+            alias: None,
             from_col: rel.from_col,
             to_col: rel.to_col,
         };
@@ -166,71 +707,721 @@ impl<C> Select<C> {
         self
     }
 
-    pub fn filter<F>(self, f: F) -> Select<C, F> {
+    /// Joins the table `rel` comes from, which is `E`'s, under `alias`, so
+    /// that the same table can be joined more than once, e.g. for
+    /// self-joins. Its columns are then referred to through `alias.col`.
+    /// Fails with `Error::RelationMismatch` if `rel` comes from another
+    /// table.
+    pub fn join_as<E: EntityTrait>(
+        mut self,
+        ty: JoinTy,
+        rel: RelationDef,
+        alias: &TableAlias<E>,
+    ) -> Result<Self, Error> {
+        if rel.from_col.tbl.as_str() != E::TABLE_NAME {
+            return Err(Error::RelationMismatch {
+                from: rel.from_col.tbl.as_str().to_string(),
+                table: E::TABLE_NAME.to_string(),
+            });
+        }
+        let join = Join {
+            ty,
+            tbl: E::TABLE_NAME.into(),
+            from_col: Col::new(alias.name().clone(), rel.from_col.col),
+            alias: Some(alias.name().clone()),
+            to_col: rel.to_col,
+        };
+        self.joins.push(join);
+        Ok(self)
+    }
+
+    /// Adds a condition to the `WHERE` clause. Calling it repeatedly
+    /// accumulates the conditions with `AND`.
+    pub fn filter<G>(self, f: G) -> Select<C, And<F, G>, H> {
         Select {
             from: self.from,
             joins: self.joins,
-            filter: f,
-            _pha: self._pha,
+            filter: And {
+                lhs: self.filter,
+                rhs: f,
+            },
+            groups: self.groups,
+            having: self.having,
+            orders: self.orders,
+            limit: self.limit,
+            offset: self.offset,
+            sel: self.sel,
         }
     }
+
+    /// Adds columns to the `GROUP BY` clause, e.g. `(Circle::Id, Circle::Name)`.
+    pub fn group_by(mut self, cols: impl Selector) -> Self {
+        self.groups.extend(cols.cols());
+        self
+    }
+
+    /// Adds a condition on the groups to the `HAVING` clause, typically on
+    /// an aggregate such as `count(Person::Id).gt(2)`. Calling it repeatedly
+    /// accumulates the conditions with `AND`.
+    pub fn having<G>(self, f: G) -> Select<C, F, And<H, G>> {
+        Select {
+            from: self.from,
+            joins: self.joins,
+            filter: self.filter,
+            groups: self.groups,
+            having: And {
+                lhs: self.having,
+                rhs: f,
+            },
+            orders: self.orders,
+            limit: self.limit,
+            offset: self.offset,
+            sel: self.sel,
+        }
+    }
+
+    pub fn order_by(self, col: impl Into<Col>, order: Order) -> Self {
+        self.push_order(col.into(), order, None)
+    }
+
+    pub fn order_by_nulls(self, col: impl Into<Col>, order: Order, nulls: Nulls) -> Self {
+        self.push_order(col.into(), order, Some(nulls))
+    }
+
+    fn push_order(mut self, col: Col, order: Order, nulls: Option<Nulls>) -> Self {
+        self.orders.push(OrderBy { col, order, nulls });
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
 }
 
-impl<'q, C: Selector, F: Filter<'q>> Select<C, F> {
-    pub fn col<D>(self, _: D) -> Select<D, F> {
+impl<C: Selector, F: Filter, H: Filter> Select<C, F, H> {
+    pub fn col<D>(self, sel: D) -> Select<D, F, H> {
+        self.cast(|_| sel)
+    }
+
+    fn cast<D>(self, f: impl FnOnce(C) -> D) -> Select<D, F, H> {
         Select {
             from: self.from,
             joins: self.joins,
             filter: self.filter,
-            _pha: PhantomData,
+            groups: self.groups,
+            having: self.having,
+            orders: self.orders,
+            limit: self.limit,
+            offset: self.offset,
+            sel: f(self.sel),
         }
     }
 
-    pub fn query(self) -> QueryBuilder<'q, Postgres> {
-        let mut builder = QueryBuilder::new(format!("{}", self));
+    pub fn query<'q, DB>(self) -> QueryBuilder<'q, DB>
+    where
+        DB: Backend,
+        F: FilterSql<'q, DB>,
+        H: FilterSql<'q, DB>,
+    {
+        self.split().1
+    }
+
+    /// Builds the query, handing back the selector to decode its rows with.
+    fn split<'q, DB>(self) -> (C, QueryBuilder<'q, DB>)
+    where
+        DB: Backend,
+        F: FilterSql<'q, DB>,
+        H: FilterSql<'q, DB>,
+    {
+        let mut builder = QueryBuilder::new("");
+        let sel = self.push_query(&mut builder);
+        (sel, builder)
+    }
+
+    fn push_query<'q, DB>(self, builder: &mut QueryBuilder<'q, DB>) -> C
+    where
+        DB: Backend,
+        F: FilterSql<'q, DB>,
+        H: FilterSql<'q, DB>,
+    {
+        builder.push(self.sql::<DB>());
         if self.filter.effective() {
             builder.push("WHERE ");
-            self.filter.filter(&mut builder);
+            self.filter.filter(builder);
+        }
+        if !self.groups.is_empty() {
+            let groups = self.groups.iter().map(|col| col.sql::<DB>()).join(", ");
+            builder.push(format!(" GROUP BY {}", groups));
+        }
+        if self.having.effective() {
+            builder.push(" HAVING ");
+            self.having.filter(builder);
+        }
+        if !self.orders.is_empty() {
+            let orders = self.orders.iter().map(|order| order.sql::<DB>()).join(", ");
+            builder.push(format!(" ORDER BY {}", orders));
+        }
+        match (self.limit, self.offset, DB::NO_LIMIT) {
+            (Some(limit), _, _) => {
+                builder.push(" LIMIT ");
+                DB::push_bind_i64(builder, limit);
+            }
+            (None, Some(_), Some(no_limit)) => {
+                builder.push(format!(" LIMIT {}", no_limit));
+            }
+            _ => {}
+        }
+        if let Some(offset) = self.offset {
+            builder.push(" OFFSET ");
+            DB::push_bind_i64(builder, offset);
+        }
+        self.sel
+    }
+
+    /// Counts the rows the query would return, keeping its joins, filters,
+    /// groups and limits.
+    pub async fn count<'q, 'c, DB, E>(self, e: E) -> Result<i64, sqlx::Error>
+    where
+        DB: Backend,
+        E: Executor<'c, Database = DB>,
+        F: FilterSql<'q, DB>,
+        H: FilterSql<'q, DB>,
+    {
+        let mut builder = QueryBuilder::new("SELECT COUNT(*) AS count FROM (");
+        self.push_query(&mut builder);
+        builder.push(") AS counted");
+        backend::fetch_one(builder, e, |row| DB::get_i64(row, "count")).await
+    }
+
+    /// Checks whether the query returns any row, without fetching it.
+    pub async fn exists<'q, 'c, DB, E>(self, e: E) -> Result<bool, sqlx::Error>
+    where
+        DB: Backend,
+        E: Executor<'c, Database = DB>,
+        F: FilterSql<'q, DB>,
+        H: FilterSql<'q, DB>,
+    {
+        let mut builder = QueryBuilder::new("SELECT EXISTS(");
+        self.push_query(&mut builder);
+        builder.push(") AS found");
+        backend::fetch_one(builder, e, |row| DB::get_bool(row, "found")).await
+    }
+
+    pub async fn one<'q, 'c, DB, E>(self, e: E) -> Result<C::Data, sqlx::Error>
+    where
+        DB: Backend,
+        E: Executor<'c, Database = DB>,
+        C: Decoder<DB>,
+        F: FilterSql<'q, DB>,
+        H: FilterSql<'q, DB>,
+    {
+        let (sel, query) = self.split();
+        backend::fetch_one(query, e, |row| sel.from_row(row)).await
+    }
+
+    pub async fn all<'q, 'c, DB, E>(self, e: E) -> Result<Vec<C::Data>, sqlx::Error>
+    where
+        DB: Backend,
+        E: Executor<'c, Database = DB>,
+        C: Decoder<DB>,
+        F: FilterSql<'q, DB>,
+        H: FilterSql<'q, DB>,
+    {
+        let (sel, query) = self.split();
+        backend::fetch_all(query, e, |row| sel.from_row(row)).await
+    }
+
+    /// Streams the selected rows, decoding each as it arrives instead of
+    /// collecting them all first.
+    pub fn stream<'q, 'c, DB, E>(
+        self,
+        e: E,
+    ) -> impl Stream<Item = Result<C::Data, sqlx::Error>> + 'c
+    where
+        DB: Backend,
+        E: Executor<'c, Database = DB> + 'c,
+        C: Decoder<DB> + 'c,
+        F: FilterSql<'q, DB> + 'c,
+        H: FilterSql<'q, DB> + 'c,
+        'q: 'c,
+    {
+        try_stream! {
+            let (sel, mut query) = self.split();
+            let mut rows = DB::fetch(&mut query, e);
+            while let Some(row) = rows.try_next().await? {
+                yield sel.from_row(&row)?;
+            }
         }
+    }
+
+    /// Streams the selected rows through a server-side cursor, fetching
+    /// `batch_size` rows at a time, so that only one batch is held in memory.
+    /// The cursor lives in a transaction begun on `conn`, which is committed
+    /// once the stream is exhausted. Only available on Postgres.
+    pub fn stream_batched<'q, 'c, A>(
+        self,
+        conn: A,
+        batch_size: i64,
+    ) -> impl Stream<Item = Result<C::Data, sqlx::Error>> + 'c
+    where
+        A: Acquire<'c, Database = Postgres> + 'c,
+        C: Decoder<Postgres> + 'c,
+        F: FilterSql<'q, Postgres> + 'c,
+        H: FilterSql<'q, Postgres> + 'c,
+        'q: 'c,
+    {
+        let cursor = format!("{}_{}", CURSOR, CURSORS.fetch_add(1, Ordering::Relaxed));
+        try_stream! {
+            let mut tx = conn.begin().await?;
+            let mut declare = QueryBuilder::new(format!("DECLARE {} NO SCROLL CURSOR FOR ", cursor));
+            let sel = self.push_query(&mut declare);
+            declare.build().execute(&mut *tx).await?;
+
+            let fetch = format!("FETCH {} FROM {}", batch_size.max(1), cursor);
+            loop {
+                let rows = sqlx::query(&fetch).fetch_all(&mut *tx).await?;
+                for row in &rows {
+                    yield sel.from_row(row)?;
+                }
+                if (rows.len() as i64) < batch_size.max(1) {
+                    break;
+                }
+            }
+            sqlx::query(&format!("CLOSE {}", cursor)).execute(&mut *tx).await?;
+            tx.commit().await?;
+        }
+    }
+}
+
+/// One page of a keyset pagination, along with the cursor to pass to the
+/// next call. `next` is `None` once the last page has been reached.
+#[derive(Debug)]
+pub struct Page<T, K> {
+    pub items: Vec<T>,
+    pub next: Option<K>,
+}
+
+impl<C: Selector, F: Filter, H: Filter> Select<C, F, H> {
+    /// Fetches up to `page_size` rows ordered by the `cursor` columns,
+    /// starting right after the row whose cursor values are `after`, or
+    /// from the beginning if `after` is `None`.
+    ///
+    /// The cursor order replaces any `order_by` of the select, since rows
+    /// have to come in cursor order for the next page to start after them.
+    /// Fails with `Error::InvalidPageSize` unless `page_size` is at least 1
+    /// and below `i64::MAX`.
+    pub async fn paginate_after<'q, 'c, DB, K, E>(
+        self,
+        cursor: K,
+        after: Option<K::Data>,
+        page_size: i64,
+        e: E,
+    ) -> Result<Page<C::Data, K::Data>, Error>
+    where
+        DB: Backend,
+        C: Decoder<DB>,
+        K: Keyset<'q, DB> + Decoder<DB>,
+        E: Executor<'c, Database = DB>,
+        F: FilterSql<'q, DB>,
+        H: FilterSql<'q, DB>,
+    {
+        // One more row than asked for tells whether there is a next page.
+        let fetched = match page_size.checked_add(1) {
+            Some(fetched) if page_size > 0 => fetched,
+            _ => return Err(Error::InvalidPageSize(page_size)),
+        };
+        let orders: Vec<_> = cursor
+            .cols()
+            .map(|col| OrderBy {
+                col,
+                order: Order::Asc,
+                nulls: None,
+            })
+            .collect();
+        let after = after.map(|data| KeysetAfter::new(&cursor, data));
+        let mut select = self.cast(|sel| (sel, cursor)).filter(after).limit(fetched);
+        select.orders = orders;
+
+        let mut rows = select.all(e).await?;
+        let has_more = rows.len() as i64 > page_size;
+        rows.truncate(page_size as usize);
+
+        let (items, mut keys): (Vec<_>, Vec<_>) = rows.into_iter().unzip();
+        let next = if has_more { keys.pop() } else { None };
+        Ok(Page { items, next })
+    }
+}
+
+impl<C: Selector, F: Filter, H: Filter> Select<C, F, H> {
+    /// Fetches the selected entities along with all of their related `E`s,
+    /// loading the related rows for the whole batch with one extra query,
+    /// or one per `InList::MAX_LEN` parents. The related rows are only cloned
+    /// for parents that share a key with another parent.
+    pub async fn load_related<'q, 'c, E, A>(
+        self,
+        conn: A,
+    ) -> Result<Vec<(C::Data, Vec<E::Data>)>, sqlx::Error>
+    where
+        A: Acquire<'c>,
+        A::Database: Backend,
+        C: Related<E> + Decoder<A::Database>,
+        E: EntityTrait + Decoder<A::Database> + Default,
+        E::Data: Clone,
+        C::Key: Default + Decoder<A::Database>,
+        <C::Key as Selector>::Data: Eq + Hash + Clone + SqlType<A::Database>,
+        Vec<<C::Key as Selector>::Data>: InList<'q, A::Database>,
+        F: FilterSql<'q, A::Database>,
+        H: FilterSql<'q, A::Database>,
+    {
+        let mut conn = conn.acquire().await?;
+        let parents = self
+            .cast(|sel| (sel, C::Key::default()))
+            .all(<A::Database as Backend>::executor(&mut conn))
+            .await?;
+        if parents.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut parents_per_key: HashMap<_, usize> = HashMap::new();
+        for (_, key) in &parents {
+            *parents_per_key.entry(key.clone()).or_default() += 1;
+        }
+        let keys: Vec<_> = parents_per_key.keys().cloned().collect();
+
+        // The column holding the parent key on the related side, selected
+        // under its own alias, since the parent key's alias may also be one
+        // of `E`'s when `E` is the parent's own table.
+        let to = C::to();
+        let via = C::via();
+        let link = match &via {
+            Some(via) => via.to_col.clone(),
+            None => to.to_col.clone(),
+        };
+        let sel = E::default();
+        let cols = sel
+            .cols()
+            .map(|col| select_col::<A::Database>(&col, &col.alias()))
+            .chain([select_col::<A::Database>(&link, &LINK.into())])
+            .join(", ");
+        let tbl = Iden::from(E::TABLE_NAME);
+        let mut sql = format!("SELECT {} FROM {}", cols, tbl.sql::<A::Database>());
+        if via.is_some() {
+            let join = Join {
+                ty: JoinTy::Inner,
+                tbl: to.from_col.tbl.clone(),
+                alias: None,
+                from_col: to.from_col,
+                to_col: to.to_col,
+            };
+            sql.push_str(&format!(" {}", join.sql::<A::Database>()));
+        }
+
+        let mut related: HashMap<<C::Key as Selector>::Data, Vec<_>> = HashMap::new();
+        let max_len = <Vec<_> as InList<'q, A::Database>>::MAX_LEN;
+        for chunk in keys.chunks(max_len) {
+            let mut builder = QueryBuilder::new(&sql);
+            builder.push(" WHERE");
+            chunk.to_vec().push_in_list(&mut builder, &link, false);
+
+            let exec = <A::Database as Backend>::executor(&mut conn);
+            let children = backend::fetch_all(builder, exec, |row| {
+                Ok((sel.from_row(row)?, A::Database::try_get(row, LINK)?))
+            })
+            .await?;
+            for (child, key) in children {
+                related.entry(key).or_default().push(child);
+            }
+        }
+
+        Ok(parents
+            .into_iter()
+            .map(|(parent, key)| {
+                let left = parents_per_key.get_mut(&key).expect("counted above");
+                *left -= 1;
+                let children = match *left {
+                    0 => related.remove(&key),
+                    _ => related.get(&key).cloned(),
+                };
+                (parent, children.unwrap_or_default())
+            })
+            .collect())
+    }
+}
+
+impl<E: EntityTrait, F: Filter> Select<E, F> {
+    /// Walks a hierarchy with `WITH RECURSIVE`, starting from the rows this
+    /// select picks with its joins and filters. A row is reached next when
+    /// its `rel.from_col` equals `rel.to_col` of a row already reached, so
+    /// with a `parent_id => id` relation, `def()` walks down to the
+    /// descendants and `def().rev()` walks up to the ancestors. Only
+    /// available on Postgres.
+    pub fn recursive(self, rel: RelationDef) -> Recursive<E, F> {
+        Recursive {
+            seed: self,
+            rel,
+            max_depth: None,
+        }
+    }
+}
+
+/// A `WITH RECURSIVE` query over the table of `E`, see `Select::recursive`.
+///
+/// Each path remembers the `(from_col, to_col)` keys of the rows it went
+/// through and stops at the first row whose keys were seen before, so cyclic
+/// data terminates. Only the keys are compared, so rows with equal contents
+/// are still told apart.
+pub struct Recursive<E, F = ()> {
+    seed: Select<E, F>,
+    rel: RelationDef,
+    max_depth: Option<i32>,
+}
+
+impl<E: DecodeEntity<Postgres>, F: Filter> Recursive<E, F> {
+    /// Stops `depth` steps away from the seed rows, which are at depth 0.
+    pub fn max_depth(mut self, depth: i32) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    fn tree() -> Iden {
+        format!("{}_tree", E::TABLE_NAME).into()
+    }
+
+    pub fn query<'q>(self) -> QueryBuilder<'q, Postgres>
+    where
+        F: FilterSql<'q, Postgres>,
+    {
+        let tbl = Iden::from(E::TABLE_NAME);
+        let tree = Self::tree();
+        let (from, to) = (&self.rel.from_col.col, &self.rel.to_col.col);
+        let key = format!("ROW({tbl}.{from}, {tbl}.{to})");
+        let mut builder = QueryBuilder::new(format!(
+            "WITH RECURSIVE {tree} AS (SELECT {tbl}.*, 0 AS __depth, ARRAY[{key}] AS __path FROM {tbl}\n"
+        ));
+        for join in self.seed.joins.iter().rev() {
+            builder.push(format!("{}\n", join));
+        }
+        if self.seed.filter.effective() {
+            builder.push("WHERE ");
+            self.seed.filter.filter(&mut builder);
+        }
+        builder.push(format!(
+            " UNION ALL SELECT {tbl}.*, {tree}.__depth + 1, {tree}.__path || {key} FROM {tbl} \
+             INNER JOIN {tree} ON {tbl}.{from} = {tree}.{to} WHERE NOT {key} = ANY({tree}.__path)"
+        ));
+        if let Some(depth) = self.max_depth {
+            builder.push(format!(" AND {tree}.__depth < "));
+            builder.push_bind(depth);
+        }
+        let cols = E::columns(&tree)
+            .iter()
+            .map(|col| select_col::<Postgres>(col, &col.alias()))
+            .join(", ");
+        builder.push(format!(
+            ")\nSELECT {cols}, {tree}.__depth AS __depth FROM {tree} ORDER BY {tree}.__depth"
+        ));
         builder
     }
 
-    pub async fn one<'c, E>(self, e: E) -> Result<C::Data, sqlx::Error>
+    /// Fetches every reachable row, seed rows included.
+    pub async fn all<'q, 'c, X>(self, e: X) -> Result<Vec<E::Row>, sqlx::Error>
     where
-        E: PgExecutor<'c>,
-        C: Selector,
+        X: PgExecutor<'c>,
+        F: FilterSql<'q, Postgres>,
     {
-        let mut query = self.query();
-        let row = query.build().fetch_one(e).await?;
-        C::from_row(&row)
+        let rows = self.all_with_depth(e).await?;
+        Ok(rows.into_iter().map(|(row, _)| row).collect())
     }
 
-    pub async fn all<'c, E>(self, e: E) -> Result<Vec<C::Data>, sqlx::Error>
+    /// Like `all`, along with how many steps away from a seed row each row
+    /// was reached.
+    pub async fn all_with_depth<'q, 'c, X>(self, e: X) -> Result<Vec<(E::Row, i32)>, sqlx::Error>
     where
-        E: PgExecutor<'c>,
-        C: Selector,
+        X: PgExecutor<'c>,
+        F: FilterSql<'q, Postgres>,
     {
-        let mut query = self.query();
+        use sqlx::Row;
 
+        let tree = Self::tree();
+        let mut query = self.query();
         query
             .build()
             .fetch(e)
-            .map(|x| x.and_then(|x| C::from_row(&x)))
+            .map(|x| x.and_then(|x| Ok((E::decode(&x, &tree)?, x.try_get("__depth")?))))
             .try_collect()
             .await
     }
 }
 
-impl<C, F> Display for Select<C, F>
+/// Alias of the parent key column selected along the related rows by
+/// `Select::load_related`.
+const LINK: &str = "__easy_orm_link";
+
+/// Prefix of the cursors declared by `Select::stream_batched`, which are
+/// numbered by `CURSORS` so that several streams can share a connection.
+const CURSOR: &str = "easy_orm_cursor";
+static CURSORS: AtomicU64 = AtomicU64::new(0);
+
+/// The name declared by the identifier `ident` of a `data_table!`, without
+/// the `r#` of a raw identifier such as `r#type`.
+#[doc(hidden)]
+pub const fn ident_name(ident: &'static str) -> &'static str {
+    match ident.as_bytes() {
+        [b'r', b'#', name @ ..] => match std::str::from_utf8(name) {
+            Ok(name) => name,
+            Err(_) => ident,
+        },
+        _ => ident,
+    }
+}
+
+fn select_col<DB: Backend>(col: &Col, alias: &Iden) -> String {
+    format!("{} AS {}", col.sql::<DB>(), alias.sql::<DB>())
+}
+
+/// Appends a `RETURNING` clause with `cols`, under the aliases a `Decoder`
+/// reads them by. Only called for backends with `Backend::RETURNING`.
+pub fn push_returning<DB: Backend>(
+    builder: &mut QueryBuilder<'_, DB>,
+    cols: impl IntoIterator<Item = Col>,
+) {
+    debug_assert!(DB::RETURNING, "RETURNING on a backend without it");
+    let cols = cols
+        .into_iter()
+        .map(|col| format!("{} AS {}", col.col.sql::<DB>(), col.alias().sql::<DB>()))
+        .join(", ");
+    builder.push(format!(" RETURNING {}", cols));
+}
+
+impl<C, F, H> ToSql for Select<C, F, H>
 where
     C: Selector,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let cols = C::cols().join(", ");
-        writeln!(f, "SELECT {} FROM {}", cols, self.from)?;
+    fn fmt_sql<DB: Backend>(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let cols = self
+            .sel
+            .cols()
+            .map(|col| select_col::<DB>(&col, &col.alias()))
+            .join(", ");
+        writeln!(f, "SELECT {} FROM {}", cols, self.from.sql::<DB>())?;
         for join in self.joins.iter().rev() {
-            writeln!(f, "{}", join)?;
+            writeln!(f, "{}", join.sql::<DB>())?;
         }
         Ok(())
     }
 }
+
+impl<C: Selector, F, H> Display for Select<C, F, H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_sql::<Postgres>(f)
+    }
+}
+
+/// What an `INSERT ... ON CONFLICT` does with the conflicting row. Rendered
+/// as `ON DUPLICATE KEY UPDATE` on MySQL, see `Backend::ON_DUPLICATE_KEY`.
+pub enum ConflictAction<U> {
+    Nothing,
+    /// Overwrites the columns with the values that were being inserted.
+    Excluded(Vec<Col>),
+    /// Applies an explicit update.
+    Set(U),
+}
+
+pub struct Delete<E, F = ()> {
+    from: Iden,
+    filter: F,
+    all: bool,
+
+    _pha: PhantomData<E>,
+}
+
+impl<E> Delete<E> {
+    pub(crate) fn new(name: Iden) -> Self {
+        Self {
+            from: name,
+            filter: (),
+            all: false,
+            _pha: PhantomData,
+        }
+    }
+}
+
+impl<E, F> Delete<E, F> {
+    /// Adds a condition to the `WHERE` clause. Calling it repeatedly
+    /// accumulates the conditions with `AND`.
+    pub fn filter<G>(self, f: G) -> Delete<E, And<F, G>> {
+        Delete {
+            from: self.from,
+            filter: And {
+                lhs: self.filter,
+                rhs: f,
+            },
+            all: self.all,
+            _pha: self._pha,
+        }
+    }
+
+    /// Allows the delete to run without any filter, removing every row of
+    /// the table.
+    pub fn delete_all(mut self) -> Self {
+        self.all = true;
+        self
+    }
+}
+
+impl<E: EntityTrait, F: Filter> Delete<E, F> {
+    /// Fails with `Error::MissingFilter` unless a filter was given or
+    /// `delete_all` was called, so a forgotten filter never wipes out a
+    /// table.
+    pub fn query<'q, DB>(self) -> Result<QueryBuilder<'q, DB>, Error>
+    where
+        DB: Backend,
+        F: FilterSql<'q, DB>,
+    {
+        let effective = self.filter.effective();
+        if !effective && !self.all {
+            return Err(Error::MissingFilter {
+                table: self.from.as_str().to_string(),
+            });
+        }
+
+        let mut builder = QueryBuilder::new(format!("DELETE FROM {}", self.from.sql::<DB>()));
+        if effective {
+            builder.push(" WHERE");
+            self.filter.filter(&mut builder);
+        }
+        Ok(builder)
+    }
+
+    /// Runs the delete, returning the number of rows deleted. Fails like
+    /// `query` without a filter.
+    pub async fn exec<'q, 'c, DB, Ex>(self, e: Ex) -> Result<u64, Error>
+    where
+        DB: Backend,
+        Ex: Executor<'c, Database = DB>,
+        F: FilterSql<'q, DB>,
+    {
+        let mut query = self.query()?;
+        Ok(DB::execute(&mut query, e).await?)
+    }
+
+    /// Runs the delete and returns the deleted rows. Fails like `query`
+    /// without a filter.
+    pub async fn returning<'q, 'c, DB, Ex>(self, e: Ex) -> Result<Vec<E::Row>, Error>
+    where
+        DB: SupportsReturning,
+        Ex: Executor<'c, Database = DB>,
+        E: DecodeEntity<DB>,
+        F: FilterSql<'q, DB>,
+    {
+        let sel = E::alias(E::TABLE_NAME);
+        let mut query = self.query()?;
+        push_returning(&mut query, sel.cols());
+        Ok(backend::fetch_all(query, e, |row| sel.from_row(row)).await?)
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt::Display;
+
+use sqlx::{pool::PoolConnection, Acquire, Connection, Pool, Postgres, Transaction};
+
+use crate::error::Error;
+
+/// SQLSTATE of `serialization_failure`, which asks for the whole transaction
+/// to be retried.
+const SERIALIZATION_FAILURE: &str = "40001";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolationLevel {
+    ReadUncommitted,
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl Display for IsolationLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let level = match self {
+            IsolationLevel::ReadUncommitted => "READ UNCOMMITTED",
+            IsolationLevel::ReadCommitted => "READ COMMITTED",
+            IsolationLevel::RepeatableRead => "REPEATABLE READ",
+            IsolationLevel::Serializable => "SERIALIZABLE",
+        };
+        f.write_str(level)
+    }
+}
+
+/// How `TransactionOptions::run` begins and retries its transaction.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionOptions {
+    isolation: Option<IsolationLevel>,
+    retries: u32,
+}
+
+impl TransactionOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the isolation level of the transaction.
+    pub fn isolation(mut self, level: IsolationLevel) -> Self {
+        self.isolation = Some(level);
+        self
+    }
+
+    /// Runs the closure again, in a fresh transaction, up to `retries` more
+    /// times when it fails with a serialization failure.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Runs `f` in a transaction on `conn`, committing if it returns `Ok` and
+    /// rolling back if it returns `Err`.
+    ///
+    /// `conn` is a pool or a connection of one, see `Outermost`, since
+    /// neither option applies to a savepoint: it shares the isolation level
+    /// of its parent, and a serialization failure dooms the parent, which
+    /// has to be retried as a whole. Nest with `transaction` instead.
+    ///
+    /// `f` returns this crate's `Error`, so both the sqlx errors and the
+    /// usage errors of the queries it runs can be propagated with `?`.
+    /// Taking an async closure requires Rust 1.85.
+    pub async fn run<'c, A, F, T>(&self, conn: A, f: F) -> Result<T, Error>
+    where
+        A: Outermost<'c>,
+        F: AsyncFnMut(&mut Transaction<'_, Postgres>) -> Result<T, Error>,
+    {
+        self.run_on(conn, f).await
+    }
+
+    async fn run_on<'c, A, F, T>(&self, conn: A, mut f: F) -> Result<T, Error>
+    where
+        A: Acquire<'c, Database = Postgres>,
+        F: AsyncFnMut(&mut Transaction<'_, Postgres>) -> Result<T, Error>,
+    {
+        let mut conn = conn.acquire().await?;
+        let mut attempt = 0;
+        loop {
+            let mut tx = Connection::begin(&mut *conn).await?;
+            let res = match self.isolation {
+                Some(level) => sqlx::query(&format!("SET TRANSACTION ISOLATION LEVEL {}", level))
+                    .execute(&mut *tx)
+                    .await
+                    .map(|_| ())
+                    .map_err(Error::from),
+                None => Ok(()),
+            };
+            let res = match res {
+                Ok(()) => f(&mut tx).await,
+                Err(e) => Err(e),
+            };
+            let res = match res {
+                Ok(val) => tx.commit().await.map(|_| val).map_err(Error::from),
+                Err(e) => {
+                    // The error that made us roll back is the one worth
+                    // reporting, and dropping `tx` rolls back regardless.
+                    let _ = tx.rollback().await;
+                    Err(e)
+                }
+            };
+            match res {
+                Err(e) if attempt < self.retries && is_serialization_failure(&e) => attempt += 1,
+                res => return res,
+            }
+        }
+    }
+}
+
+/// What `TransactionOptions::run` begins its transaction on, which can't be
+/// in a transaction already: a pool, or a connection checked out of one.
+/// The `tx` of an enclosing transaction, or the connection it derefs to,
+/// isn't one.
+pub trait Outermost<'c>: Acquire<'c, Database = Postgres> {}
+
+impl<'c> Outermost<'c> for &Pool<Postgres> {}
+
+impl<'c> Outermost<'c> for &'c mut PoolConnection<Postgres> {}
+
+fn is_serialization_failure(e: &Error) -> bool {
+    match e {
+        Error::Sqlx(sqlx::Error::Database(e)) => e.code().as_deref() == Some(SERIALIZATION_FAILURE),
+        _ => false,
+    }
+}
+
+/// Runs `f` in a transaction on `conn` with the default options, see
+/// `TransactionOptions::run`. If `conn` is itself a transaction, e.g. the
+/// `tx` of an enclosing call, a savepoint is used instead. Requires Rust
+/// 1.85, like `run`.
+pub async fn transaction<'c, A, F, T>(conn: A, f: F) -> Result<T, Error>
+where
+    A: Acquire<'c, Database = Postgres>,
+    F: AsyncFnMut(&mut Transaction<'_, Postgres>) -> Result<T, Error>,
+{
+    TransactionOptions::default().run_on(conn, f).await
+}
@@ -0,0 +1,6 @@
+/// The errors `#[derive(Entity)]` reports for misused `#[orm]` attributes.
+#[test]
+fn derive_errors() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,12 @@
+use easy_orm::Entity;
+
+#[derive(Debug, Clone, sqlx::FromRow, Entity)]
+#[orm(table = "people")]
+pub struct Person {
+    #[orm(primary_key)]
+    pub id: i32,
+    #[orm(unique)]
+    pub name: String,
+}
+
+fn main() {}